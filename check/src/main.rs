@@ -0,0 +1,358 @@
+//! Checks what a kindle shows against a golden image, for testing UIs on a
+//! real device from CI. It streams from the kindle like the desktop viewer,
+//! waits for the screen to settle and compares it, or a region of it, pixel
+//! by pixel. Exits with 0 if it matches, 1 if it doesn't and 2 if it couldn't
+//! tell.
+//!
+//! Pairing keys are read from the working directory like the desktop does,
+//! so run it from the same place, or use `--connect`.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use kinshare_client::Image;
+use kinshare_server::{DeviceId, DeviceMessage, Message, Screenshot};
+use kinshare_shared::{
+    messages::{Info, Rect, Rotation},
+    transport::Direct,
+};
+use tokio::{
+    sync::mpsc,
+    time::{self, Instant},
+};
+
+const USAGE: &str = "usage: kinshare-check [--connect <address>] [--device <id>] \
+[--region <name | x,y,width,height>] [--regions <file.json>] [--tolerance <levels>] \
+[--allowed <pixels>] [--settle <ms>] [--timeout <s>] [--diff <file.png>] <golden.png | golden.pgm>";
+
+#[derive(Debug)]
+struct Options {
+    golden: PathBuf,
+    /// Stream from the kindle listening here instead of a paired one.
+    direct: Option<Direct>,
+    /// Start of the id of the paired kindle to check, if there are several.
+    device: Option<String>,
+    /// A name from `regions`, or the bounds the desktop copies.
+    region: Option<String>,
+    /// JSON object of named regions in framebuffer pixels.
+    regions: Option<PathBuf>,
+    /// Gray levels a pixel may be off by.
+    tolerance: u8,
+    /// Differing pixels still counted as a match.
+    allowed: usize,
+    /// How long the screen has to stay the same before it's compared.
+    settle: Duration,
+    timeout: Duration,
+    /// Where the diff image goes, next to the golden one by default.
+    diff: Option<PathBuf>,
+}
+
+impl Options {
+    fn from_args() -> Result<Self, String> {
+        let mut args = std::env::args().skip(1);
+        let mut options = Self {
+            golden: PathBuf::new(),
+            direct: None,
+            device: None,
+            region: None,
+            regions: None,
+            tolerance: 0,
+            allowed: 0,
+            settle: Duration::from_secs(1),
+            timeout: Duration::from_secs(60),
+            diff: None,
+        };
+        let mut golden = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+            let number = |value: String| {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("{arg} needs a number, not '{value}'"))
+            };
+
+            match arg.as_str() {
+                "--connect" => {
+                    let value = value()?;
+                    options.direct = Some(Direct::parse(&value).ok_or(format!(
+                        "'{value}' isn't an address, expected tcp:<host[:port]> or unix:<path>"
+                    ))?);
+                }
+                "--device" => options.device = Some(value()?.to_ascii_lowercase()),
+                "--region" => options.region = Some(value()?),
+                "--regions" => options.regions = Some(value()?.into()),
+                "--tolerance" => {
+                    options.tolerance = number(value()?)?
+                        .try_into()
+                        .map_err(|_| "--tolerance is at most 255".to_owned())?;
+                }
+                "--allowed" => options.allowed = number(value()?)? as usize,
+                "--settle" => options.settle = Duration::from_millis(number(value()?)?),
+                "--timeout" => options.timeout = Duration::from_secs(number(value()?)?),
+                "--diff" => options.diff = Some(value()?.into()),
+                _ if arg.starts_with("--") => return Err(format!("unknown argument '{arg}'")),
+                _ if golden.is_none() => golden = Some(arg.into()),
+                _ => return Err("only one golden image can be checked at a time".to_owned()),
+            }
+        }
+
+        if options.direct.is_some() && options.device.is_some() {
+            return Err("--device picks a paired kindle, it can't go with --connect".to_owned());
+        }
+
+        options.golden = golden.ok_or("no golden image given")?;
+
+        Ok(options)
+    }
+
+    fn diff_path(&self) -> PathBuf {
+        self.diff.clone().unwrap_or_else(|| {
+            let stem = self
+                .golden
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy();
+            self.golden.with_file_name(format!("{stem}.diff.png"))
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match Options::from_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match runtime.block_on(check(&options)) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Whether the kindle's screen matches the golden image.
+async fn check(options: &Options) -> anyhow::Result<bool> {
+    // Both are read up front, so mistakes show before waiting on the kindle.
+    let region = match &options.region {
+        Some(region) => Some(resolve_region(region, options.regions.as_deref())?),
+        None => None,
+    };
+    let golden = Image::load(&options.golden)?;
+    let expected = Screenshot {
+        width: golden.width as usize,
+        height: golden.height as usize,
+        pixels: golden.pixels,
+    };
+
+    let screenshot = settled_screenshot(options, region).await?;
+    let difference = screenshot
+        .compare(&expected, options.tolerance)
+        .with_context(|| format!("can't compare with '{}'", options.golden.display()))?;
+
+    if difference.differing <= options.allowed {
+        println!(
+            "Matches '{}', no pixel is off by more than {}",
+            options.golden.display(),
+            difference.largest
+        );
+
+        return Ok(true);
+    }
+
+    let diff = options.diff_path();
+    difference.save(&diff)?;
+
+    println!(
+        "Doesn't match '{}': {} of {} pixels are off by more than {}, by up to {}. Differences are marked in '{}'",
+        options.golden.display(),
+        difference.differing,
+        difference.width * difference.height,
+        options.tolerance,
+        difference.largest,
+        diff.display()
+    );
+
+    Ok(false)
+}
+
+/// `region` as a name from the `regions` file, or as `x,y,width,height`.
+fn resolve_region(region: &str, regions: Option<&Path>) -> anyhow::Result<Rect> {
+    let bounds = region
+        .split(',')
+        .map(|value| value.trim().parse::<usize>())
+        .collect::<Result<Vec<_>, _>>();
+
+    if let Ok(&[x, y, width, height]) = bounds.as_deref() {
+        return Ok(Rect {
+            x,
+            y,
+            width,
+            height,
+        });
+    }
+
+    let path = regions.with_context(|| {
+        format!("'{region}' isn't x,y,width,height, and there's no --regions file to look it up in")
+    })?;
+    let json =
+        std::fs::read(path).with_context(|| format!("failed to read '{}'", path.display()))?;
+    let mut regions = serde_json::from_slice::<HashMap<String, Rect>>(&json)
+        .with_context(|| format!("invalid '{}'", path.display()))?;
+
+    regions
+        .remove(region)
+        .with_context(|| format!("no region named '{region}' in '{}'", path.display()))
+}
+
+/// What's been received of the kindle's screen so far.
+struct Stream {
+    info: Info,
+    framebuffer: Arc<Mutex<Box<[u8]>>>,
+    rotation: Rotation,
+    /// When the screen last changed, `None` until the first frame.
+    changed: Option<Instant>,
+}
+
+/// Stream from the kindle until its screen hasn't changed for
+/// `options.settle`, then capture `region` of it.
+async fn settled_screenshot(options: &Options, region: Option<Rect>) -> anyhow::Result<Screenshot> {
+    let (sender, mut messages) = mpsc::unbounded_channel();
+    let mut server = tokio::spawn(kinshare_server::run(
+        sender,
+        kinshare_server::Options {
+            record_dir: None,
+            direct: options.direct.clone(),
+        },
+    ));
+
+    let result = tokio::select! {
+        result = &mut server => {
+            result??;
+            bail!("stopped streaming before the screen settled")
+        }
+        result = time::timeout(options.timeout, settle(options, &mut messages)) => result,
+    };
+
+    server.abort();
+
+    let stream = result.map_err(|_| {
+        anyhow::anyhow!(
+            "the screen didn't settle within {}s",
+            options.timeout.as_secs()
+        )
+    })??;
+
+    Screenshot::capture(
+        &stream.info,
+        &stream.framebuffer.lock().unwrap(),
+        stream.rotation,
+        region,
+    )
+}
+
+/// Follow the server's messages until the screen settles.
+async fn settle(
+    options: &Options,
+    messages: &mut mpsc::UnboundedReceiver<Message>,
+) -> anyhow::Result<Stream> {
+    let mut device = None;
+    let mut stream = None::<Stream>;
+
+    loop {
+        let settled = stream
+            .as_ref()
+            .and_then(|stream| stream.changed)
+            .map(|changed| changed + options.settle);
+
+        let message = tokio::select! {
+            message = messages.recv() => message.context("the server stopped")?,
+            () = time::sleep_until(settled.unwrap_or_else(Instant::now)), if settled.is_some() => {
+                return Ok(stream.take().unwrap());
+            }
+        };
+
+        match message {
+            Message::Message(message) => println!("{message}"),
+            Message::Devices(devices) => device = Some(pick_device(options, &devices)?),
+            Message::Device(id, message) if Some(id) == device => match message {
+                DeviceMessage::Incompatible(err) => bail!("the kindle can't be checked: {err}"),
+                DeviceMessage::Connected {
+                    info, framebuffer, ..
+                }
+                | DeviceMessage::Reconfigured { info, framebuffer } => {
+                    stream = Some(Stream {
+                        info,
+                        framebuffer,
+                        rotation: Rotation::default(),
+                        changed: None,
+                    });
+                }
+                DeviceMessage::Rotated(rotation) => {
+                    if let Some(stream) = &mut stream {
+                        stream.rotation = rotation;
+                        stream.changed = Some(Instant::now());
+                    }
+                }
+                DeviceMessage::Updated { chunks } if !chunks.is_empty() => {
+                    if let Some(stream) = &mut stream {
+                        stream.changed = Some(Instant::now());
+                    }
+                }
+                DeviceMessage::Closed => stream = None,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+/// The kindle to check out of the paired ones.
+fn pick_device(options: &Options, devices: &[DeviceId]) -> anyhow::Result<DeviceId> {
+    let matching = devices
+        .iter()
+        .filter(|device| {
+            options
+                .device
+                .as_ref()
+                .is_none_or(|prefix| device.to_string().starts_with(prefix))
+        })
+        .collect::<Vec<_>>();
+
+    match matching.as_slice() {
+        [device] => Ok(**device),
+        [] if devices.is_empty() => bail!("no kindles paired yet, pair one with kinshare-desktop"),
+        [] => bail!("no paired kindle's id starts with the one given"),
+        _ => bail!(
+            "several kindles match, pick one with --device: {}",
+            matching
+                .iter()
+                .map(|device| device.fmt_short().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
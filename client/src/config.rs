@@ -0,0 +1,144 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail, ensure};
+use kinshare_shared::{codec::CodecId, messages::Info, transport::Direct};
+use serde::Deserialize;
+use tokio::fs;
+
+use crate::{
+    pacing::Pacing,
+    source::{FrameSource, SourceConfig},
+};
+
+const CONFIG_FILE: &str = "stream.json";
+
+/// Where the extension keeps its files. `dir` or `KINSHARE_DIR` points it
+/// somewhere else, for running the client off the kindle.
+pub(crate) fn data_dir(dir: Option<&Path>) -> PathBuf {
+    match dir {
+        Some(dir) => dir.to_owned(),
+        None => std::env::var_os("KINSHARE_DIR")
+            .map_or_else(|| "/mnt/us/extensions/kinshare".into(), PathBuf::from),
+    }
+}
+
+/// Optional overrides from `stream.json`. Anything left out is worked out
+/// from the panel itself.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    display_width: Option<usize>,
+    display_height: Option<usize>,
+    chunks_per_x: Option<usize>,
+    chunks_per_y: Option<usize>,
+    thread_count: Option<usize>,
+    fps: Option<f64>,
+    /// Slow down to `idle_fps` while nothing changes, and below `fps` while
+    /// the link can't keep up. On by default.
+    adaptive: Option<bool>,
+    /// Capture rate once the screen has settled, 2 by default.
+    idle_fps: Option<f64>,
+    /// Frames in a row without changes before slowing down, one second's
+    /// worth at `fps` by default.
+    idle_after: Option<u32>,
+    /// Preferred chunk codec, LZ4 if unset or the desktop doesn't know it.
+    codec: Option<CodecId>,
+    /// Send changed chunks XORed against their previous contents, on by
+    /// default.
+    delta: Option<bool>,
+    /// Where frames come from, the framebuffer unless set.
+    source: Option<SourceConfig>,
+    /// Listen for desktops over plain TCP or a Unix socket instead of iroh.
+    transport: Option<Direct>,
+}
+
+impl Config {
+    pub(crate) async fn load(dir: &Path) -> anyhow::Result<Self> {
+        let path = dir.join(CONFIG_FILE);
+
+        match fs::read(&path).await {
+            Ok(data) => {
+                serde_json::from_slice(&data).with_context(|| format!("invalid {}", path.display()))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub(crate) fn codec(&self) -> CodecId {
+        self.codec.unwrap_or_default()
+    }
+
+    pub(crate) fn delta(&self) -> bool {
+        self.delta.unwrap_or(true)
+    }
+
+    /// How to pace the stream, `None` to always capture at `fps`.
+    pub(crate) fn pacing(&self) -> anyhow::Result<Option<Pacing>> {
+        if !self.adaptive.unwrap_or(true) {
+            return Ok(None);
+        }
+
+        let pacing = Pacing {
+            idle_fps: self.idle_fps.unwrap_or(2.0),
+            idle_after: self.idle_after,
+        };
+
+        ensure!(
+            pacing.idle_fps.is_finite() && pacing.idle_fps > 0.0,
+            "idle_fps must be positive"
+        );
+        ensure!(
+            pacing.idle_after != Some(0),
+            "idle_after must be at least 1"
+        );
+
+        Ok(Some(pacing))
+    }
+
+    pub(crate) fn transport(&self) -> Option<&Direct> {
+        self.transport.as_ref()
+    }
+
+    pub(crate) fn source(&self) -> &SourceConfig {
+        self.source.as_ref().unwrap_or(&SourceConfig::Framebuffer)
+    }
+
+    /// Fill in the stream parameters, checking any overrides against the real
+    /// panel geometry.
+    pub(crate) fn resolve(&self, source: &dyn FrameSource) -> anyhow::Result<Info> {
+        let (panel_width, panel_height) = source.size();
+        let (panel_width, panel_height) = (panel_width as usize, panel_height as usize);
+
+        let info = Info {
+            display_width: self.display_width.unwrap_or(panel_width),
+            display_height: self.display_height.unwrap_or(panel_height),
+            chunks_per_x: self.chunks_per_x.unwrap_or(8),
+            chunks_per_y: self.chunks_per_y.unwrap_or(8),
+            thread_count: self.thread_count.unwrap_or(2),
+            fps: self.fps.unwrap_or(60.0),
+        };
+
+        if info.display_width > panel_width || info.display_height > panel_height {
+            bail!(
+                "stream.json asks for {}x{} but the panel is only {panel_width}x{panel_height}",
+                info.display_width,
+                info.display_height
+            );
+        }
+
+        if (info.display_width, info.display_height) != (panel_width, panel_height) {
+            println!(
+                "Capturing {}x{} of the {panel_width}x{panel_height} panel",
+                info.display_width, info.display_height
+            );
+        }
+
+        info.validate().context("invalid stream.json")?;
+
+        Ok(info)
+    }
+}
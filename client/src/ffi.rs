@@ -72,3 +72,27 @@ pub(super) struct FbFixScreeninfo {
     pub(super) capabilities: u16,
     pub(super) reserved: [u16; 2],
 }
+
+// uinput ioctl numbers (see <linux/uinput.h>). Only the legacy setup path is
+// used, by writing a `uinput_user_dev`, since UI_DEV_SETUP needs kernel 4.5+.
+pub(super) const UI_DEV_CREATE: libc::c_ulong = 0x5501;
+pub(super) const UI_DEV_DESTROY: libc::c_ulong = 0x5502;
+pub(super) const UI_SET_EVBIT: libc::c_ulong = 0x40045564;
+pub(super) const UI_SET_KEYBIT: libc::c_ulong = 0x40045565;
+pub(super) const UI_SET_ABSBIT: libc::c_ulong = 0x40045567;
+pub(super) const UI_SET_PROPBIT: libc::c_ulong = 0x4004556e;
+
+// Event types and codes (see <linux/input-event-codes.h>).
+pub(super) const EV_SYN: u16 = 0x00;
+pub(super) const EV_KEY: u16 = 0x01;
+pub(super) const EV_ABS: u16 = 0x03;
+pub(super) const SYN_REPORT: u16 = 0x00;
+pub(super) const BTN_TOUCH: u16 = 0x14a;
+pub(super) const ABS_X: u16 = 0x00;
+pub(super) const ABS_Y: u16 = 0x01;
+pub(super) const ABS_MT_SLOT: u16 = 0x2f;
+pub(super) const ABS_MT_POSITION_X: u16 = 0x35;
+pub(super) const ABS_MT_POSITION_Y: u16 = 0x36;
+pub(super) const ABS_MT_TRACKING_ID: u16 = 0x39;
+pub(super) const INPUT_PROP_DIRECT: u16 = 0x01;
+pub(super) const BUS_VIRTUAL: u16 = 0x06;
@@ -1,4 +1,7 @@
-use std::os::fd::AsRawFd;
+use std::{
+    ops::Range,
+    os::fd::{AsRawFd, RawFd},
+};
 
 use crate::ffi::{FBIOGET_FSCREENINFO, FBIOGET_VSCREENINFO, FbFixScreeninfo, FbVarScreeninfo};
 
@@ -26,20 +29,25 @@ impl Framebuffer {
             .write(true)
             .open("/dev/fb0")?;
 
-        let fd = file.as_raw_fd();
+        let vinfo = var_screeninfo(file.as_raw_fd())?;
 
-        let mut vinfo = FbVarScreeninfo::default();
+        let mut framebuffer = Self {
+            file,
+            map: std::ptr::null_mut(),
+            len: 0,
+            width: 0,
+            height: 0,
+            stride: 0,
+        };
+        framebuffer.map(&vinfo)?;
 
-        if unsafe {
-            libc::ioctl(
-                fd,
-                FBIOGET_VSCREENINFO as _,
-                &raw mut vinfo as *mut libc::c_void,
-            )
-        } == -1
-        {
-            return Err(std::io::Error::last_os_error());
-        }
+        Ok(framebuffer)
+    }
+
+    /// Map the framebuffer with the geometry in `vinfo`, replacing the
+    /// current mapping only once the new one is in place.
+    fn map(&mut self, vinfo: &FbVarScreeninfo) -> std::io::Result<()> {
+        let fd = self.as_raw_fd();
 
         let mut finfo = FbFixScreeninfo::default();
 
@@ -95,19 +103,90 @@ impl Framebuffer {
             return Err(std::io::Error::last_os_error());
         }
 
-        Ok(Self {
-            file,
-            map: map as *mut u8,
-            len,
-            width,
-            height,
-            stride,
-        })
+        self.unmap();
+
+        self.map = map as *mut u8;
+        self.len = len;
+        self.width = width;
+        self.height = height;
+        self.stride = stride;
+
+        Ok(())
+    }
+
+    fn unmap(&mut self) {
+        if !self.map.is_null() {
+            unsafe { libc::munmap(self.map as *mut libc::c_void, self.len) };
+        }
     }
 }
 
+impl Framebuffer {
+    /// Copy the leftmost `width` pixels of each row in `rows` into `out`,
+    /// dropping the stride padding so `out` ends up tightly packed.
+    pub(crate) fn copy_rows(&self, rows: Range<usize>, width: usize, out: &mut [u8]) {
+        assert!(rows.end <= self.height as usize && width <= self.width as usize);
+
+        for (row, out) in rows.zip(out.chunks_exact_mut(width)) {
+            // SAFETY: bounds are checked above and the mapping lives as long as
+            // `self`. The EPDC may be writing concurrently, same as with `pread`.
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    self.map.add(row * self.stride),
+                    out.as_mut_ptr(),
+                    width,
+                );
+            }
+        }
+    }
+}
+
+impl Framebuffer {
+    /// Re-query the panel rotation, which the framework can change at any time.
+    ///
+    /// Rotating can swap the visible resolution too, in which case the
+    /// framebuffer is mapped again and `width`, `height` and `stride` follow.
+    pub(crate) fn rotation(&mut self) -> std::io::Result<u32> {
+        let vinfo = var_screeninfo(self.as_raw_fd())?;
+
+        if vinfo.xres != self.width || vinfo.yres != self.height {
+            self.map(&vinfo)?;
+        }
+
+        Ok(vinfo.rotate)
+    }
+}
+
+fn var_screeninfo(fd: RawFd) -> std::io::Result<FbVarScreeninfo> {
+    let mut vinfo = FbVarScreeninfo::default();
+
+    if unsafe {
+        libc::ioctl(
+            fd,
+            FBIOGET_VSCREENINFO as _,
+            &raw mut vinfo as *mut libc::c_void,
+        )
+    } == -1
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(vinfo)
+}
+
+impl AsRawFd for Framebuffer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+// The mapping is only ever read through `copy_rows`, which the capture threads
+// call on disjoint row ranges.
+unsafe impl Send for Framebuffer {}
+unsafe impl Sync for Framebuffer {}
+
 impl Drop for Framebuffer {
     fn drop(&mut self) {
-        unsafe { libc::munmap(self.map as *mut libc::c_void, self.len) };
+        self.unmap();
     }
 }
@@ -0,0 +1,162 @@
+use std::{
+    io::{self, Write},
+    os::fd::AsRawFd,
+};
+
+use kinshare_shared::messages::{InputEvent, TouchPhase};
+
+use crate::ffi::{
+    ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_MT_SLOT, ABS_MT_TRACKING_ID, ABS_X, ABS_Y, BTN_TOUCH,
+    BUS_VIRTUAL, EV_ABS, EV_KEY, EV_SYN, INPUT_PROP_DIRECT, SYN_REPORT, UI_DEV_CREATE,
+    UI_DEV_DESTROY, UI_SET_ABSBIT, UI_SET_EVBIT, UI_SET_KEYBIT, UI_SET_PROPBIT,
+};
+
+/// Virtual touchscreen and keyboard, created through `/dev/uinput`, that
+/// replays input coming from the viewer.
+pub(crate) struct Injector {
+    file: std::fs::File,
+    tracking_id: i32,
+}
+
+impl Injector {
+    /// Create the device with an absolute range matching the framebuffer, so
+    /// touch coordinates can be passed through as-is.
+    pub(crate) fn open(width: u32, height: u32) -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open("/dev/uinput")?;
+
+        let fd = file.as_raw_fd();
+        let set = |request: libc::c_ulong, value: u16| {
+            if unsafe { libc::ioctl(fd, request as _, value as libc::c_int) } == -1 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        };
+
+        set(UI_SET_EVBIT, EV_SYN)?;
+        set(UI_SET_EVBIT, EV_KEY)?;
+        set(UI_SET_EVBIT, EV_ABS)?;
+        set(UI_SET_PROPBIT, INPUT_PROP_DIRECT)?;
+        set(UI_SET_KEYBIT, BTN_TOUCH)?;
+
+        // Everything from KEY_ESC up to the media keys, the viewer only ever
+        // sends codes from this range.
+        for code in 1..256 {
+            set(UI_SET_KEYBIT, code)?;
+        }
+
+        for code in [
+            ABS_X,
+            ABS_Y,
+            ABS_MT_SLOT,
+            ABS_MT_POSITION_X,
+            ABS_MT_POSITION_Y,
+            ABS_MT_TRACKING_ID,
+        ] {
+            set(UI_SET_ABSBIT, code)?;
+        }
+
+        let mut device: libc::uinput_user_dev = unsafe { std::mem::zeroed() };
+
+        for (dst, src) in device.name.iter_mut().zip(b"kinshare remote input") {
+            *dst = *src as libc::c_char;
+        }
+
+        device.id.bustype = BUS_VIRTUAL;
+        device.id.vendor = 0x1;
+        device.id.product = 0x1;
+        device.id.version = 1;
+
+        for (code, max) in [
+            (ABS_X, width - 1),
+            (ABS_Y, height - 1),
+            (ABS_MT_POSITION_X, width - 1),
+            (ABS_MT_POSITION_Y, height - 1),
+            (ABS_MT_TRACKING_ID, u16::MAX as u32),
+        ] {
+            device.absmax[code as usize] = max as i32;
+        }
+
+        (&file).write_all(unsafe {
+            std::slice::from_raw_parts(
+                (&raw const device).cast::<u8>(),
+                size_of::<libc::uinput_user_dev>(),
+            )
+        })?;
+
+        if unsafe { libc::ioctl(fd, UI_DEV_CREATE as _) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            file,
+            tracking_id: 0,
+        })
+    }
+
+    pub(crate) fn inject(&mut self, event: InputEvent) -> io::Result<()> {
+        match event {
+            InputEvent::Touch { phase, x, y } => {
+                let (x, y) = (x as i32, y as i32);
+
+                match phase {
+                    TouchPhase::Down => {
+                        self.tracking_id = (self.tracking_id + 1) % u16::MAX as i32;
+
+                        self.write(&[
+                            (EV_ABS, ABS_MT_SLOT, 0),
+                            (EV_ABS, ABS_MT_TRACKING_ID, self.tracking_id),
+                            (EV_ABS, ABS_MT_POSITION_X, x),
+                            (EV_ABS, ABS_MT_POSITION_Y, y),
+                            (EV_KEY, BTN_TOUCH, 1),
+                            (EV_ABS, ABS_X, x),
+                            (EV_ABS, ABS_Y, y),
+                            (EV_SYN, SYN_REPORT, 0),
+                        ])
+                    }
+                    TouchPhase::Move => self.write(&[
+                        (EV_ABS, ABS_MT_POSITION_X, x),
+                        (EV_ABS, ABS_MT_POSITION_Y, y),
+                        (EV_ABS, ABS_X, x),
+                        (EV_ABS, ABS_Y, y),
+                        (EV_SYN, SYN_REPORT, 0),
+                    ]),
+                    TouchPhase::Up => self.write(&[
+                        (EV_ABS, ABS_MT_TRACKING_ID, -1),
+                        (EV_KEY, BTN_TOUCH, 0),
+                        (EV_SYN, SYN_REPORT, 0),
+                    ]),
+                }
+            }
+            InputEvent::Key { code, pressed } => {
+                self.write(&[(EV_KEY, code, pressed as i32), (EV_SYN, SYN_REPORT, 0)])
+            }
+        }
+    }
+
+    fn write(&mut self, events: &[(u16, u16, i32)]) -> io::Result<()> {
+        let events = events
+            .iter()
+            .map(|&(type_, code, value)| {
+                // The kernel stamps the time itself.
+                let mut event: libc::input_event = unsafe { std::mem::zeroed() };
+                event.type_ = type_;
+                event.code = code;
+                event.value = value;
+                event
+            })
+            .collect::<Vec<_>>();
+
+        self.file.write_all(unsafe {
+            std::slice::from_raw_parts(events.as_ptr().cast::<u8>(), size_of_val(events.as_slice()))
+        })
+    }
+}
+
+impl Drop for Injector {
+    fn drop(&mut self) {
+        unsafe { libc::ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY as _) };
+    }
+}
@@ -0,0 +1,76 @@
+use std::{io, path::Path};
+
+use anyhow::Context;
+use iroh::{EndpointId, SecretKey};
+use tokio::fs;
+
+const KEY_FILE: &str = "kindle.key";
+const SERVERS_FILE: &str = "servers";
+
+/// Our own secret key, generated the first time it's needed. It never leaves
+/// the kindle.
+pub(crate) async fn kindle_key(dir: &Path) -> anyhow::Result<SecretKey> {
+    let path = dir.join(KEY_FILE);
+
+    match fs::read(&path).await {
+        Ok(bytes) => {
+            let bytes = bytes.try_into().map_err(|_| {
+                anyhow::anyhow!("'{}' is corrupt, delete it and pair again", path.display())
+            })?;
+
+            Ok(SecretKey::from_bytes(&bytes))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let key = SecretKey::generate();
+
+            fs::write(&path, key.to_bytes())
+                .await
+                .with_context(|| format!("failed to write '{}'", path.display()))?;
+
+            Ok(key)
+        }
+        Err(err) => Err(err).with_context(|| format!("failed to read '{}'", path.display())),
+    }
+}
+
+/// Public keys of every desktop we've been paired with, 32 bytes each.
+pub(crate) async fn servers(dir: &Path) -> anyhow::Result<Vec<EndpointId>> {
+    let path = dir.join(SERVERS_FILE);
+
+    let bytes = match fs::read(&path).await {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read '{}'", path.display()));
+        }
+    };
+
+    anyhow::ensure!(
+        bytes.len() % 32 == 0,
+        "'{}' is corrupt, delete it and pair again",
+        path.display()
+    );
+
+    bytes
+        .chunks_exact(32)
+        .map(|key| Ok(EndpointId::from_bytes(key.try_into()?)?))
+        .collect()
+}
+
+pub(crate) async fn add_server(dir: &Path, server: EndpointId) -> anyhow::Result<()> {
+    let path = dir.join(SERVERS_FILE);
+    let mut servers = servers(dir).await?;
+
+    if !servers.contains(&server) {
+        servers.push(server);
+    }
+
+    let bytes = servers
+        .iter()
+        .flat_map(|server| *server.as_bytes())
+        .collect::<Vec<u8>>();
+
+    fs::write(&path, bytes)
+        .await
+        .with_context(|| format!("failed to write '{}'", path.display()))
+}
@@ -0,0 +1,512 @@
+use std::{
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use anyhow::{Context, bail};
+
+use iroh::{
+    Endpoint, EndpointId,
+    endpoint::{QuicTransportConfig, presets},
+};
+use iroh_mdns_address_lookup::MdnsAddressLookup;
+use kinshare_shared::{
+    codec::{self, CodecId},
+    consts::ALPN,
+    messages::{self, Capabilities, Chunk, Control, Encoding, Hello, Info, Rotation, Settings},
+    transport::{DirectListener, Reader, Writer},
+};
+use tokio::{
+    sync::mpsc,
+    time::{self, Duration, Instant},
+};
+
+use crate::{input::Injector, pacing::Pacer};
+
+pub use config::Config;
+pub use source::{FrameSource, Image};
+
+mod config;
+mod ffi;
+mod framebuffer;
+mod input;
+mod keys;
+mod pacing;
+mod pair;
+mod screen;
+mod source;
+
+const CAPABILITIES: Capabilities = Capabilities::CODEC_LZ4
+    .union(Capabilities::CODEC_GRAY4)
+    .union(Capabilities::CODEC_RLE)
+    .union(Capabilities::CODEC_ZSTD)
+    .union(Capabilities::FORMAT_GRAY8)
+    .union(Capabilities::DELTA)
+    .union(Capabilities::RECONFIGURE);
+
+/// Opens a fresh [`FrameSource`] for every connection.
+pub type OpenSource = Box<dyn Fn() -> anyhow::Result<Box<dyn FrameSource>> + Send + Sync>;
+
+pub struct Options {
+    /// Stream from this instead of the source in `stream.json`.
+    pub source: Option<OpenSource>,
+    /// Offer remote input through uinput. Off when not running on a kindle,
+    /// the desktop would be typing into whatever machine we're on.
+    pub input: bool,
+    /// Use this instead of loading `stream.json` for every connection.
+    pub config: Option<Config>,
+    /// Where keys and `stream.json` live, see [`pair`] for the default.
+    pub dir: Option<PathBuf>,
+}
+
+/// Wait for desktops to connect and stream to them, one at a time. Paired
+/// desktops come in over iroh, unless `stream.json` picks another transport.
+pub async fn run(options: Options) -> anyhow::Result<()> {
+    let dir = config::data_dir(options.dir.as_deref());
+    let config = match &options.config {
+        Some(config) => config.clone(),
+        None => Config::load(&dir).await?,
+    };
+
+    if let Some(direct) = config.transport() {
+        let listener = direct
+            .bind()
+            .await
+            .with_context(|| format!("failed to listen on {direct}"))?;
+
+        println!("Listening on {direct}");
+
+        return serve_direct(listener, options).await;
+    }
+
+    let kindle_key = keys::kindle_key(&dir).await?;
+    let servers = keys::servers(&dir).await?;
+
+    if servers.is_empty() {
+        screen::show("Kinshare isn't paired, use Pair Kinshare");
+        bail!("not paired with any computer yet, run 'Pair Kinshare' from KUAL first");
+    }
+
+    let endpoint = Endpoint::builder(presets::N0)
+        .secret_key(kindle_key)
+        .address_lookup(MdnsAddressLookup::builder())
+        .transport_config(
+            QuicTransportConfig::builder()
+                .max_idle_timeout(Some(Duration::from_secs(10).try_into()?))
+                .build(),
+        )
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await?;
+
+    println!("Endpoint id: {}", endpoint.id().to_z32());
+
+    serve(endpoint, &servers, options).await
+}
+
+/// Stream to whichever of `servers` connects to `endpoint`, one at a time.
+/// The endpoint has to accept [`ALPN`].
+pub async fn serve(
+    endpoint: Endpoint,
+    servers: &[EndpointId],
+    options: Options,
+) -> anyhow::Result<()> {
+    while let Some(incoming) = endpoint.accept().await {
+        let Ok(connection) = incoming.await else {
+            continue;
+        };
+
+        if !servers.contains(&connection.remote_id()) {
+            println!(
+                "Unpaired computer tried connecting to us: {}",
+                connection.remote_id()
+            );
+
+            connection.close(0u8.into(), b"Unauthorized");
+            continue;
+        }
+
+        println!("Connected to: {}", connection.remote_id());
+
+        let result = match connection.open_bi().await {
+            Ok((send, recv)) => stream_to(Box::new(recv), Box::new(send), &options).await,
+            Err(err) => Err(err.into()),
+        };
+
+        if let Err(err) = result {
+            eprintln!("Error streaming: {err:#?}");
+        }
+
+        connection.close(0u8.into(), &[]);
+    }
+
+    Ok(())
+}
+
+/// Stream to whoever connects through `listener`, one at a time. Remote input
+/// is never offered, nothing proves who's on the other end.
+pub async fn serve_direct(listener: DirectListener, options: Options) -> anyhow::Result<()> {
+    let options = Options {
+        input: false,
+        ..options
+    };
+
+    loop {
+        let (reader, writer, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                eprintln!("Error accepting a connection: {err}");
+                time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        println!("Connected to: {peer}");
+
+        if let Err(err) = stream_to(reader, writer, &options).await {
+            eprintln!("Error streaming: {err:#?}");
+        }
+    }
+}
+
+/// Stream over an already connected channel, `reader` carrying the desktop's
+/// control messages. Only returns once something goes wrong, usually the
+/// desktop going away.
+pub async fn stream_to(reader: Reader, writer: Writer, options: &Options) -> anyhow::Result<()> {
+    let stream = Stream::new(reader, writer, options)
+        .await
+        .context("failed to start the stream")?;
+
+    stream.run().await
+}
+
+/// Show a pairing code and wait for a desktop to enter it. Keys are kept in
+/// `dir`, or `KINSHARE_DIR`, or the extension's directory on the kindle.
+pub async fn pair(dir: Option<&Path>) -> anyhow::Result<()> {
+    pair::run(&config::data_dir(dir)).await
+}
+
+struct Stream {
+    info: Info,
+    source: Box<dyn FrameSource>,
+    /// Size of `source` that `info` was worked out for.
+    panel: (u32, u32),
+    stream: Writer,
+    screen: Box<[u8]>,
+    chunks: Box<[Chunk]>,
+    encode_buffers: Box<[Box<[u8]>]>,
+    pacer: Pacer,
+    /// Last rotation sent, `None` until the first frame goes out.
+    rotation: Option<Rotation>,
+    /// The source's rotation that's sent as upright. Once the size followed a
+    /// rotation, the pixels are already laid out for it.
+    upright: Rotation,
+    encoding: Encoding,
+    capabilities: Capabilities,
+    /// Shared with the task reading the desktop's input, `None` without it.
+    injector: Arc<Mutex<Option<Injector>>>,
+    /// Settings the desktop asked for, applied before the next capture.
+    settings: mpsc::UnboundedReceiver<Settings>,
+}
+
+impl Stream {
+    async fn new(
+        mut control: Reader,
+        mut stream: Writer,
+        options: &Options,
+    ) -> anyhow::Result<Self> {
+        let config = match &options.config {
+            Some(config) => config.clone(),
+            None => Config::load(&config::data_dir(options.dir.as_deref())).await?,
+        };
+        let source = match &options.source {
+            Some(open_source) => open_source()?,
+            None => config.source().open()?,
+        };
+        let info = config.resolve(&*source)?;
+
+        println!("Starting stream with config: {info:#?}");
+
+        let screen = vec![0; info.display_size()].into_boxed_slice();
+        let chunks = chunks(&info);
+        let encode_buffers = encode_buffers(&info);
+        let pacer = Pacer::new(info.fps, config.pacing()?);
+
+        // Remote input is optional, only offer it when we can actually inject.
+        let (width, height) = source.size();
+        let (injector, local) = match options.input.then(|| Injector::open(width, height)) {
+            Some(Ok(injector)) => (Some(injector), CAPABILITIES.union(Capabilities::INPUT)),
+            Some(Err(err)) => {
+                eprintln!("Remote input unavailable: {err}");
+                (None, CAPABILITIES)
+            }
+            None => (None, CAPABILITIES),
+        };
+
+        messages::write_hello(&mut stream, &Hello::new(local)).await?;
+        let hello = messages::read_hello(&mut control).await?;
+        let capabilities = local.negotiate(&hello)?;
+
+        println!("Negotiated capabilities: {capabilities:?}");
+
+        let codec = if capabilities.contains(config.codec().capability()) {
+            config.codec()
+        } else {
+            println!("Desktop doesn't support {:?}, using LZ4", config.codec());
+            CodecId::Lz4
+        };
+
+        let encoding = Encoding {
+            codec,
+            delta: config.delta() && capabilities.contains(Capabilities::DELTA),
+        };
+
+        messages::write_info(&mut stream, &info).await?;
+
+        let (settings_sender, settings) = mpsc::unbounded_channel();
+        let injector = Arc::new(Mutex::new(
+            injector.filter(|_| capabilities.contains(Capabilities::INPUT)),
+        ));
+
+        if injector.lock().unwrap().is_some() || capabilities.contains(Capabilities::RECONFIGURE) {
+            let injector = Arc::clone(&injector);
+
+            tokio::spawn(async move {
+                // Ends along with the connection.
+                loop {
+                    match messages::read_control(&mut control).await {
+                        Ok(Control::Input(event)) => {
+                            let mut injector = injector.lock().unwrap();
+                            let Some(injector) = &mut *injector else {
+                                continue;
+                            };
+
+                            if let Err(err) = injector.inject(event) {
+                                eprintln!("Error injecting input: {err}");
+                            }
+                        }
+                        Ok(Control::Reconfigure(settings)) => {
+                            if settings_sender.send(settings).is_err() {
+                                break;
+                            }
+                        }
+                        Err(_) => break,
+                    }
+                }
+            });
+        }
+
+        Ok(Self {
+            info,
+            source,
+            panel: (width, height),
+            stream,
+            screen,
+            chunks,
+            encode_buffers,
+            pacer,
+            rotation: None,
+            upright: Rotation::Upright,
+            encoding,
+            capabilities,
+            injector,
+            settings,
+        })
+    }
+
+    async fn run(mut self) -> anyhow::Result<()> {
+        loop {
+            self.pacer.tick().await;
+
+            // Only the latest request matters if several came in since the
+            // last frame.
+            let mut settings = None;
+
+            while let Ok(latest) = self.settings.try_recv() {
+                settings = Some(latest);
+            }
+
+            if let Some(settings) = settings {
+                self.reconfigure(settings).await?;
+            }
+
+            let rotation = self.source.advance()?;
+
+            if self.source.size() != self.panel {
+                self.resize(rotation).await?;
+            }
+
+            let rotation = rotation.relative_to(self.upright);
+
+            let info = &self.info;
+            let source = &*self.source;
+            let encoding = self.encoding;
+            // Each thread captures and encodes whole rows of chunks.
+            let band_chunks = info.chunks_per_y.div_ceil(info.thread_count) * info.chunks_per_x;
+
+            thread::scope(|s| {
+                let mut screen = &mut self.screen[..];
+                let mut top = 0;
+
+                for (chunks, buffer) in self
+                    .chunks
+                    .chunks_mut(band_chunks)
+                    .zip(self.encode_buffers.iter_mut())
+                {
+                    let last = &chunks[chunks.len() - 1];
+                    let bottom = info.chunk_rect(last.x, last.y).bottom();
+
+                    let (framebuffer, rest) =
+                        mem::take(&mut screen).split_at_mut((bottom - top) * info.display_width);
+                    screen = rest;
+
+                    let rows = top..bottom;
+                    top = bottom;
+
+                    s.spawn(move || {
+                        let file_offset = rows.start * info.display_width;
+
+                        source.copy_rows(rows, info.display_width, framebuffer);
+
+                        for chunk in chunks.iter_mut() {
+                            messages::encode_chunk(
+                                info,
+                                file_offset,
+                                framebuffer,
+                                buffer,
+                                encoding,
+                                chunk,
+                            );
+                        }
+                    });
+                }
+            });
+
+            let updated = self.chunks.iter().filter(|c| c.updated).count();
+
+            let mut send_time = Duration::ZERO;
+
+            if updated != 0 || self.rotation != Some(rotation) {
+                let start = Instant::now();
+
+                messages::write_frame(&mut self.stream, rotation, &mut self.chunks, updated)
+                    .await?;
+
+                send_time = start.elapsed();
+                self.rotation = Some(rotation);
+            }
+
+            self.pacer.frame(updated != 0, send_time);
+        }
+    }
+
+    /// Switch to `settings` between two frames, starting over from blank
+    /// chunks like a new connection would.
+    async fn reconfigure(&mut self, settings: Settings) -> anyhow::Result<()> {
+        let info = self.info.with_settings(settings);
+
+        if let Err(err) = info.validate() {
+            eprintln!("Ignoring settings from the desktop: {err}");
+            return Ok(());
+        }
+
+        println!("Switching to {settings:?}");
+
+        self.restart(info).await
+    }
+
+    /// Follow the source to its new size, like after a rotation that swaps
+    /// the panel's width and height. A stream of the whole panel stays one,
+    /// a smaller area and the chunk grid shrink if they no longer fit.
+    /// `rotation` came along with the new size, so it's upright from now on.
+    async fn resize(&mut self, rotation: Rotation) -> anyhow::Result<()> {
+        let (width, height) = self.source.size();
+        let (width, height) = (width as usize, height as usize);
+        let (old_width, old_height) = self.panel;
+
+        if !self.capabilities.contains(Capabilities::RECONFIGURE) {
+            bail!(
+                "panel changed from {old_width}x{old_height} to {width}x{height} \
+                and the desktop can't switch to it mid-stream"
+            );
+        }
+
+        let fit = |display: usize, old: u32, panel: usize| {
+            if display == old as usize {
+                panel
+            } else {
+                display.min(panel)
+            }
+        };
+        let display_width = fit(self.info.display_width, old_width, width);
+        let display_height = fit(self.info.display_height, old_height, height);
+
+        let info = Info {
+            display_width,
+            display_height,
+            chunks_per_x: self.info.chunks_per_x.min(display_width),
+            chunks_per_y: self.info.chunks_per_y.min(display_height),
+            ..self.info.clone()
+        };
+        info.validate()?;
+
+        println!("Panel changed from {old_width}x{old_height} to {width}x{height}");
+
+        // Touches are passed through as panel coordinates, so the device's
+        // range has to follow the panel. It can't be changed once created.
+        {
+            let mut injector = self.injector.lock().unwrap();
+
+            if injector.take().is_some() {
+                match Injector::open(width as u32, height as u32) {
+                    Ok(reopened) => *injector = Some(reopened),
+                    Err(err) => eprintln!("Remote input unavailable: {err}"),
+                }
+            }
+        }
+
+        self.panel = self.source.size();
+        self.upright = rotation;
+
+        self.restart(info).await
+    }
+
+    /// Tell the desktop about `info` and stream by it from the next frame.
+    async fn restart(&mut self, info: Info) -> anyhow::Result<()> {
+        messages::write_reconfigured(&mut self.stream, &info).await?;
+
+        self.screen = vec![0; info.display_size()].into_boxed_slice();
+        self.chunks = chunks(&info);
+        self.encode_buffers = encode_buffers(&info);
+        self.pacer.set_fps(info.fps);
+        self.info = info;
+        // Send the next frame even if it's all blank, so the desktop has
+        // something to show.
+        self.rotation = None;
+
+        Ok(())
+    }
+}
+
+/// Blank chunks for `info`'s grid, all sent in full the first time.
+fn chunks(info: &Info) -> Box<[Chunk]> {
+    (0..info.chunk_count())
+        .map(|i| Chunk {
+            x: i % info.chunks_per_x,
+            y: i / info.chunks_per_x,
+            hash: 0,
+            encoded: vec![0; codec::max_encoded_len(info.chunk_size())].into_boxed_slice(),
+            encoded_len: 0,
+            encoding: Encoding::default(),
+            previous: None,
+            updated: false,
+        })
+        .collect()
+}
+
+/// Scratch space for every capture thread.
+fn encode_buffers(info: &Info) -> Box<[Box<[u8]>]> {
+    vec![vec![0; info.chunk_size()].into_boxed_slice(); info.thread_count].into_boxed_slice()
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use tokio::time::{self, Instant, Interval, MissedTickBehavior};
+
+/// How far below its configured rate a stream slows down while the screen
+/// stays the same, from `stream.json`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Pacing {
+    pub(crate) idle_fps: f64,
+    /// Frames in a row without a single changed chunk before slowing down,
+    /// a second's worth unless set.
+    pub(crate) idle_after: Option<u32>,
+}
+
+/// Decides when the next frame is captured. Runs at the stream's fps while
+/// the screen changes, drops to the idle rate once it settles, and backs off
+/// whenever sending a frame takes longer than the link can keep up with.
+/// Without [`Pacing`] it's just the stream's fps.
+pub(crate) struct Pacer {
+    fps: f64,
+    pacing: Option<Pacing>,
+    /// Frames in a row that changed nothing.
+    quiet: u32,
+    /// Highest rate the link has kept up with lately, if it's been slower
+    /// than `fps`.
+    cap: Option<f64>,
+    /// Rate `interval` ticks at.
+    current: f64,
+    interval: Interval,
+}
+
+/// Rate the cap climbs back by on every frame that sent quickly, so a link
+/// that recovered gets back to full speed within a second or two.
+const RECOVERY: f64 = 1.1;
+
+impl Pacer {
+    pub(crate) fn new(fps: f64, pacing: Option<Pacing>) -> Self {
+        Self {
+            fps,
+            pacing,
+            quiet: 0,
+            cap: None,
+            current: fps,
+            interval: interval(fps, Instant::now()),
+        }
+    }
+
+    /// Start over at `fps`, after the stream was reconfigured.
+    pub(crate) fn set_fps(&mut self, fps: f64) {
+        *self = Self::new(fps, self.pacing);
+    }
+
+    pub(crate) async fn tick(&mut self) {
+        self.interval.tick().await;
+    }
+
+    /// Account for the frame just captured. `changed` is whether anything had
+    /// to be sent, `send_time` how long writing it to the stream took.
+    pub(crate) fn frame(&mut self, changed: bool, send_time: Duration) {
+        self.quiet = if changed {
+            0
+        } else {
+            self.quiet.saturating_add(1)
+        };
+
+        // Off means off, a slow link just makes frames wait for the send.
+        if changed && self.pacing.is_some() {
+            let period = 1.0 / self.current;
+            let send_time = send_time.as_secs_f64();
+
+            // Writes only take this long once the send buffer is full, any
+            // faster and frames would just pile up behind it.
+            if send_time > period {
+                self.cap = Some((1.0 / send_time).min(self.fps));
+            } else if let Some(cap) = self.cap
+                && send_time < period / 2.0
+            {
+                self.cap = Some(cap * RECOVERY).filter(|&cap| cap < self.fps);
+            }
+        }
+
+        let idle = self
+            .pacing
+            .filter(|pacing| self.quiet >= pacing.idle_after.unwrap_or(self.fps.ceil() as u32));
+
+        let target = match idle {
+            Some(pacing) => pacing.idle_fps.min(self.fps),
+            None => self.cap.unwrap_or(self.fps),
+        };
+
+        if target != self.current {
+            self.current = target;
+            self.interval = interval(
+                target,
+                Instant::now() + Duration::from_secs_f64(1.0 / target),
+            );
+        }
+    }
+}
+
+fn interval(fps: f64, start: Instant) -> Interval {
+    let mut interval = time::interval_at(start, Duration::from_secs_f64(1.0 / fps));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    interval
+}
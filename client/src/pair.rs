@@ -0,0 +1,134 @@
+use std::{io::Read, path::Path, time::Duration};
+
+use anyhow::{Context, bail};
+use iroh::{Endpoint, EndpointId, endpoint::presets};
+use iroh_mdns_address_lookup::MdnsAddressLookup;
+use kinshare_shared::{
+    consts::{PAIRING_ALPN, PAIRING_SERVICE},
+    messages,
+    pairing::{self, Role},
+};
+use tokio::time;
+
+use crate::{keys, screen};
+
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Failed attempts we put up with before giving up, so the code can't be
+/// guessed by whoever else is on the network.
+const MAX_ATTEMPTS: usize = 3;
+
+/// Advertise ourselves on the local network and wait for a desktop to prove
+/// it knows the code shown on screen. Only its public key is stored.
+pub(crate) async fn run(dir: &Path) -> anyhow::Result<()> {
+    let key = keys::kindle_key(dir).await?;
+
+    let endpoint = Endpoint::builder(presets::N0)
+        .secret_key(key)
+        .address_lookup(MdnsAddressLookup::builder().service_name(PAIRING_SERVICE))
+        .alpns(vec![PAIRING_ALPN.to_vec()])
+        .bind()
+        .await?;
+
+    let result = time::timeout(PAIRING_TIMEOUT, accept(&endpoint, dir)).await;
+
+    endpoint.close().await;
+
+    match result {
+        Ok(Ok(server)) => {
+            screen::show(&format!("Kinshare paired with {}", server.fmt_short()));
+            Ok(())
+        }
+        Ok(Err(err)) => {
+            screen::show("Kinshare pairing failed, check log");
+            Err(err)
+        }
+        Err(_) => {
+            screen::show("Kinshare pairing timed out");
+            bail!("nobody entered the pairing code in time")
+        }
+    }
+}
+
+async fn accept(endpoint: &Endpoint, dir: &Path) -> anyhow::Result<EndpointId> {
+    let mut attempts = 0;
+    let mut code = pairing_code()?;
+
+    show_code("Kinshare pairing code", code);
+
+    while let Some(incoming) = endpoint.accept().await {
+        let Ok(connection) = incoming.await else {
+            continue;
+        };
+
+        let Ok((mut send, mut recv)) = connection.accept_bi().await else {
+            continue;
+        };
+
+        let Ok(commitment) = messages::read_pairing_commitment(&mut recv).await else {
+            continue;
+        };
+
+        let server = connection.remote_id();
+        let (desktop, kindle) = (server.as_bytes(), endpoint.id());
+        let proof = pairing::proof(code, Role::Kindle, desktop, kindle.as_bytes());
+        let expected = pairing::proof(code, Role::Desktop, desktop, kindle.as_bytes());
+
+        // Past this point the code may have leaked through our proof, so it's
+        // either paired or done with.
+        let accepted = messages::write_pairing_proof(&mut send, &proof)
+            .await
+            .is_ok()
+            && messages::read_pairing_opening(&mut recv)
+                .await
+                .is_ok_and(|opening| opening.opens(&commitment, &expected));
+
+        // A desktop that didn't like our proof has hung up already.
+        if messages::write_pairing_result(&mut send, accepted)
+            .await
+            .is_ok()
+        {
+            send.finish().ok();
+        }
+
+        if accepted {
+            keys::add_server(dir, server).await?;
+
+            // Let the desktop read the answer before going away.
+            time::timeout(Duration::from_secs(5), connection.closed())
+                .await
+                .ok();
+
+            return Ok(server);
+        }
+
+        println!("Failed pairing attempt from {server}");
+        connection.close(0u8.into(), b"Wrong code");
+
+        attempts += 1;
+
+        if attempts >= MAX_ATTEMPTS {
+            bail!("too many failed pairing attempts");
+        }
+
+        code = pairing_code()?;
+        show_code("Kinshare pairing failed, new code", code);
+    }
+
+    bail!("endpoint closed while pairing")
+}
+
+fn show_code(message: &str, code: u32) {
+    screen::show(&format!("{message}: {:03} {:03}", code / 1000, code % 1000));
+}
+
+/// Six random digits.
+fn pairing_code() -> anyhow::Result<u32> {
+    let mut bytes = [0; 4];
+
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(&mut bytes))
+        .context("failed to read /dev/urandom")?;
+
+    Ok(u32::from_ne_bytes(bytes) % 1_000_000)
+}
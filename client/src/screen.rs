@@ -0,0 +1,12 @@
+use std::process::Command;
+
+/// Print a line on the e-ink screen with `eips`, the same way the KUAL
+/// scripts report their status. This is only a courtesy, so failures are
+/// just logged.
+pub(crate) fn show(message: &str) {
+    println!("{message}");
+
+    if let Err(err) = Command::new("eips").args(["3", "5", message]).status() {
+        eprintln!("Failed to run eips: {err}");
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    fs,
+    io::BufReader,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, bail, ensure};
+use kinshare_shared::messages::Rotation;
+use serde::Deserialize;
+
+use crate::framebuffer::Framebuffer;
+
+/// Where the stream captures frames from.
+pub trait FrameSource: Send + Sync {
+    /// Full size of the image, the stream may capture less of it.
+    fn size(&self) -> (u32, u32);
+
+    /// Called once per tick before capturing, returns the current rotation.
+    /// The size may change along with it.
+    fn advance(&mut self) -> anyhow::Result<Rotation>;
+
+    /// Copy the leftmost `width` pixels of each row in `rows` into `out`,
+    /// tightly packed. Called from several threads on disjoint rows.
+    fn copy_rows(&self, rows: Range<usize>, width: usize, out: &mut [u8]);
+}
+
+/// The `source` section of `stream.json`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) enum SourceConfig {
+    /// The kindle's own screen.
+    Framebuffer,
+    /// A single binary PGM, or raw 8-bit gray pixels if `width` and `height`
+    /// are given.
+    File {
+        path: PathBuf,
+        width: Option<u32>,
+        height: Option<u32>,
+    },
+    /// Every PNG in a directory, in name order, looping.
+    PngDir {
+        path: PathBuf,
+        /// Seconds each image stays up, 1 by default.
+        frame_time: Option<f64>,
+    },
+    /// Generated test pattern with a bar moving down the screen.
+    Pattern { width: u32, height: u32 },
+}
+
+impl SourceConfig {
+    pub(crate) fn open(&self) -> anyhow::Result<Box<dyn FrameSource>> {
+        Ok(match self {
+            Self::Framebuffer => {
+                Box::new(Framebuffer::open().context("failed to open the framebuffer")?)
+            }
+            Self::File {
+                path,
+                width,
+                height,
+            } => Box::new(load_file(path, *width, *height)?),
+            Self::PngDir { path, frame_time } => Box::new(PngDir::open(
+                path,
+                Duration::from_secs_f64(frame_time.unwrap_or(1.0)),
+            )?),
+            Self::Pattern { width, height } => {
+                ensure!(*width > 0 && *height > 0, "pattern size can't be zero");
+
+                Box::new(Pattern {
+                    width: *width,
+                    height: *height,
+                    frame: 0,
+                })
+            }
+        })
+    }
+}
+
+impl FrameSource for Framebuffer {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn advance(&mut self) -> anyhow::Result<Rotation> {
+        Ok(Rotation::from_raw(self.rotation()?))
+    }
+
+    fn copy_rows(&self, rows: Range<usize>, width: usize, out: &mut [u8]) {
+        Framebuffer::copy_rows(self, rows, width, out);
+    }
+}
+
+/// 8-bit grayscale pixels held in memory, shown as-is.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Load a PNG or binary PGM, picked by extension.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match extension.as_deref() {
+            Some("png") => load_png(path),
+            Some("pgm") => fs::read(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| parse_pgm(&data)),
+            _ => bail!("only PNG and PGM images are supported"),
+        }
+        .with_context(|| format!("invalid '{}'", path.display()))
+    }
+}
+
+impl FrameSource for Image {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn advance(&mut self) -> anyhow::Result<Rotation> {
+        Ok(Rotation::Upright)
+    }
+
+    fn copy_rows(&self, rows: Range<usize>, width: usize, out: &mut [u8]) {
+        let stride = self.width as usize;
+
+        for (row, out) in rows.zip(out.chunks_exact_mut(width)) {
+            out.copy_from_slice(&self.pixels[row * stride..row * stride + width]);
+        }
+    }
+}
+
+fn load_file(path: &Path, width: Option<u32>, height: Option<u32>) -> anyhow::Result<Image> {
+    let data = fs::read(path).with_context(|| format!("failed to read '{}'", path.display()))?;
+
+    match (width, height) {
+        (Some(width), Some(height)) => {
+            ensure!(
+                data.len() == width as usize * height as usize,
+                "'{}' is {} bytes, expected {width}x{height}",
+                path.display(),
+                data.len()
+            );
+
+            Ok(Image {
+                width,
+                height,
+                pixels: data,
+            })
+        }
+        (None, None) => parse_pgm(&data).with_context(|| format!("invalid '{}'", path.display())),
+        _ => bail!("raw files need both a width and a height"),
+    }
+}
+
+fn parse_pgm(data: &[u8]) -> anyhow::Result<Image> {
+    let mut rest = data.strip_prefix(b"P5").context("not a binary PGM")?;
+    let mut fields = [0; 3];
+
+    for field in &mut fields {
+        // Whitespace and comments can go anywhere in the header.
+        loop {
+            match rest.first() {
+                Some(b'#') => {
+                    let line = rest.iter().position(|&c| c == b'\n').unwrap_or(rest.len());
+                    rest = &rest[line..];
+                }
+                Some(c) if c.is_ascii_whitespace() => rest = &rest[1..],
+                _ => break,
+            }
+        }
+
+        let digits = rest.iter().take_while(|c| c.is_ascii_digit()).count();
+        ensure!(digits > 0, "malformed header");
+
+        *field = std::str::from_utf8(&rest[..digits])?.parse::<u32>()?;
+        rest = &rest[digits..];
+    }
+
+    // A single whitespace byte separates the header from the pixels.
+    let rest = rest.get(1..).context("truncated header")?;
+    let [width, height, max] = fields;
+    let len = width as usize * height as usize;
+
+    ensure!((1..=255).contains(&max), "only 8-bit PGMs are supported");
+    ensure!(width > 0 && height > 0, "image is empty");
+    ensure!(rest.len() >= len, "image is truncated");
+
+    let pixels = rest[..len]
+        .iter()
+        .map(|&pixel| (pixel as u32 * 255 / max) as u8)
+        .collect();
+
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn load_png(path: &Path) -> anyhow::Result<Image> {
+    let file = fs::File::open(path)?;
+
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size().context("image is too large")?];
+    let frame = reader.next_frame(&mut buffer)?;
+
+    let channels = frame.color_type.samples();
+    let pixels = buffer[..frame.buffer_size()]
+        .chunks_exact(channels)
+        .map(|pixel| match pixel {
+            // Alpha is ignored, the panel has nothing to blend with.
+            [gray] | [gray, _] => *gray,
+            [r, g, b, ..] => ((*r as u32 * 299 + *g as u32 * 587 + *b as u32 * 114) / 1000) as u8,
+            [] => 0,
+        })
+        .collect();
+
+    Ok(Image {
+        width: frame.width,
+        height: frame.height,
+        pixels,
+    })
+}
+
+struct PngDir {
+    paths: Vec<PathBuf>,
+    frame_time: Duration,
+    current: usize,
+    shown: Instant,
+    image: Image,
+}
+
+impl PngDir {
+    fn open(dir: &Path, frame_time: Duration) -> anyhow::Result<Self> {
+        let mut paths = fs::read_dir(dir)
+            .with_context(|| format!("failed to read '{}'", dir.display()))?
+            .map(|entry| Ok(entry?.path()))
+            .filter(|path: &std::io::Result<PathBuf>| {
+                path.as_ref().map_or(true, |path| {
+                    path.extension()
+                        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+                })
+            })
+            .collect::<std::io::Result<Vec<PathBuf>>>()?;
+
+        paths.sort();
+
+        let first = paths
+            .first()
+            .with_context(|| format!("no PNGs in '{}'", dir.display()))?;
+        let image = load_png(first).with_context(|| format!("invalid '{}'", first.display()))?;
+
+        Ok(Self {
+            paths,
+            frame_time,
+            current: 0,
+            shown: Instant::now(),
+            image,
+        })
+    }
+}
+
+impl FrameSource for PngDir {
+    fn size(&self) -> (u32, u32) {
+        self.image.size()
+    }
+
+    fn advance(&mut self) -> anyhow::Result<Rotation> {
+        if self.paths.len() > 1 && self.shown.elapsed() >= self.frame_time {
+            self.current = (self.current + 1) % self.paths.len();
+            self.shown = Instant::now();
+
+            let path = &self.paths[self.current];
+            let image = load_png(path).with_context(|| format!("invalid '{}'", path.display()))?;
+
+            ensure!(
+                image.size() == self.image.size(),
+                "'{}' is {}x{}, every image has to be {}x{}",
+                path.display(),
+                image.width,
+                image.height,
+                self.image.width,
+                self.image.height
+            );
+
+            self.image = image;
+        }
+
+        Ok(Rotation::Upright)
+    }
+
+    fn copy_rows(&self, rows: Range<usize>, width: usize, out: &mut [u8]) {
+        self.image.copy_rows(rows, width, out);
+    }
+}
+
+/// White page with a 16 level gradient across the top and a black bar moving
+/// down, so some chunks change every frame and others never do.
+struct Pattern {
+    width: u32,
+    height: u32,
+    frame: usize,
+}
+
+impl FrameSource for Pattern {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn advance(&mut self) -> anyhow::Result<Rotation> {
+        self.frame += 1;
+
+        Ok(Rotation::Upright)
+    }
+
+    fn copy_rows(&self, rows: Range<usize>, width: usize, out: &mut [u8]) {
+        let (full_width, height) = (self.width as usize, self.height as usize);
+        let gradient = height / 16;
+        let bar = (self.frame * 8) % height;
+
+        for (row, out) in rows.zip(out.chunks_exact_mut(width)) {
+            if row < gradient {
+                for (x, pixel) in out.iter_mut().enumerate() {
+                    *pixel = (x * 16 / full_width) as u8 * 0x11;
+                }
+            } else if (bar..bar + 32).contains(&row) {
+                out.fill(0x00);
+            } else {
+                out.fill(0xff);
+            }
+        }
+    }
+}
@@ -0,0 +1,674 @@
+//! Runs the kindle and desktop sides of a stream in one process, over two
+//! iroh endpoints talking directly on localhost or a plain socket, and checks
+//! that the desktop ends up with exactly what the kindle captured.
+
+use std::{
+    net::SocketAddr,
+    ops::Range,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use iroh::{Endpoint, RelayMode, SecretKey, address_lookup::MemoryLookup, endpoint::presets};
+use kinshare_client::{Config, FrameSource, Options};
+use kinshare_server::{DeviceMessage, FrameStats, Message};
+use kinshare_shared::{
+    consts::ALPN,
+    messages::{Info, Rotation, Settings},
+    transport::{Direct, DirectListener},
+};
+use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle, time};
+
+/// How long anything is allowed to take before the test fails.
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Pixels the test draws into, captured the way the kindle's framebuffer is.
+#[derive(Clone)]
+struct Screen {
+    /// What the stream captured last, `mode` from then on.
+    width: u32,
+    height: u32,
+    mode: Arc<Mutex<(u32, u32, Rotation)>>,
+    pixels: Arc<Mutex<Vec<u8>>>,
+    /// Frames captured so far.
+    captures: Arc<AtomicUsize>,
+}
+
+impl Screen {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            mode: Arc::new(Mutex::new((width, height, Rotation::Upright))),
+            pixels: Arc::new(Mutex::new(vec![0; width as usize * height as usize])),
+            captures: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn show(&self, pixels: &[u8]) {
+        self.pixels.lock().unwrap().copy_from_slice(pixels);
+    }
+
+    /// Turn clockwise and swap the width and height from the next capture
+    /// on, like a kernel that lays the pixels out for the new rotation.
+    fn rotate(&self) {
+        let mut mode = self.mode.lock().unwrap();
+        *mode = (mode.1, mode.0, Rotation::from_raw(mode.2 as u32 + 1));
+    }
+}
+
+impl FrameSource for Screen {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn advance(&mut self) -> anyhow::Result<Rotation> {
+        self.captures.fetch_add(1, Ordering::Relaxed);
+        let (width, height, rotation) = *self.mode.lock().unwrap();
+        (self.width, self.height) = (width, height);
+
+        Ok(rotation)
+    }
+
+    fn copy_rows(&self, rows: Range<usize>, width: usize, out: &mut [u8]) {
+        let pixels = self.pixels.lock().unwrap();
+        let stride = self.width as usize;
+
+        for (row, out) in rows.zip(out.chunks_exact_mut(width)) {
+            out.copy_from_slice(&pixels[row * stride..row * stride + width]);
+        }
+    }
+}
+
+/// Both ends of one stream, the kindle can be restarted and the viewer
+/// reconnected independently.
+struct Harness {
+    screen: Screen,
+    config: Config,
+    kindle_key: SecretKey,
+    /// Where the kindle listens, kept across restarts like a real device's
+    /// address would be.
+    kindle_addr: SocketAddr,
+    kindle: Option<(Endpoint, JoinHandle<anyhow::Result<()>>)>,
+    server: Endpoint,
+    lookup: MemoryLookup,
+    viewer: Viewer,
+}
+
+/// The desktop side, following whatever the viewer reports.
+struct Viewer {
+    task: JoinHandle<anyhow::Result<()>>,
+    messages: mpsc::UnboundedReceiver<Message>,
+    framebuffer: Option<Arc<Mutex<Box<[u8]>>>>,
+    info: Option<Info>,
+    reconfigure: Option<mpsc::UnboundedSender<Settings>>,
+    rotation: Rotation,
+    stats: Vec<FrameStats>,
+    /// Coordinates of every chunk the frames so far updated.
+    chunks: Vec<(u8, u8)>,
+    /// Frames received since the last connection was made or the kindle was
+    /// reconfigured.
+    updates: usize,
+}
+
+impl Harness {
+    async fn start(screen: Screen, config: &str) -> Self {
+        let lookup = MemoryLookup::new();
+        let server = Endpoint::builder(presets::Minimal)
+            .relay_mode(RelayMode::Disabled)
+            .address_lookup(lookup.clone())
+            .bind_addr("127.0.0.1:0")
+            .unwrap()
+            .bind()
+            .await
+            .unwrap();
+
+        let kindle_key = SecretKey::generate();
+        let viewer = spawn_viewer(&server, &kindle_key);
+
+        let mut harness = Self {
+            screen,
+            config: serde_json::from_str(config).unwrap(),
+            kindle_key,
+            kindle_addr: "127.0.0.1:0".parse().unwrap(),
+            kindle: None,
+            server,
+            lookup,
+            viewer,
+        };
+
+        harness.start_kindle().await;
+        harness
+    }
+
+    async fn start_kindle(&mut self) {
+        let endpoint = Endpoint::builder(presets::Minimal)
+            .secret_key(self.kindle_key.clone())
+            .relay_mode(RelayMode::Disabled)
+            .alpns(vec![ALPN.to_vec()])
+            .bind_addr(self.kindle_addr)
+            .unwrap()
+            .bind()
+            .await
+            .unwrap();
+
+        self.kindle_addr = endpoint.bound_sockets()[0];
+        self.lookup.set_endpoint_info(endpoint.addr());
+
+        let options = options(&self.screen, &self.config);
+        let servers = [self.server.id()];
+
+        let task = tokio::spawn({
+            let endpoint = endpoint.clone();
+            async move { kinshare_client::serve(endpoint, &servers, options).await }
+        });
+
+        self.kindle = Some((endpoint, task));
+    }
+
+    async fn stop_kindle(&mut self) {
+        let (endpoint, task) = self.kindle.take().unwrap();
+
+        // The task holds on to a clone of the endpoint, and with it the
+        // socket the restarted kindle binds again, until it's really gone.
+        task.abort();
+        task.await.ok();
+        endpoint.close().await;
+    }
+
+    /// Drop the viewer's connection and start over, like the desktop app
+    /// being restarted.
+    fn restart_viewer(&mut self) {
+        self.viewer.task.abort();
+        self.viewer = spawn_viewer(&self.server, &self.kindle_key);
+    }
+
+    async fn expect(&mut self, pixels: &[u8]) {
+        self.viewer.expect(&self.screen, pixels).await;
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        if let Some((_, task)) = &self.kindle {
+            task.abort();
+        }
+    }
+}
+
+impl Viewer {
+    fn new(
+        task: JoinHandle<anyhow::Result<()>>,
+        messages: mpsc::UnboundedReceiver<Message>,
+    ) -> Self {
+        Self {
+            task,
+            messages,
+            framebuffer: None,
+            info: None,
+            reconfigure: None,
+            rotation: Rotation::Upright,
+            stats: Vec::new(),
+            chunks: Vec::new(),
+            updates: 0,
+        }
+    }
+
+    /// Handle the next message from the viewer.
+    async fn next(&mut self) {
+        let message = time::timeout(TIMEOUT, self.messages.recv())
+            .await
+            .expect("timed out waiting for the viewer")
+            .expect("viewer stopped");
+
+        let Message::Device(_, message) = message else {
+            return;
+        };
+
+        match message {
+            DeviceMessage::Connected {
+                info,
+                framebuffer,
+                reconfigure,
+                ..
+            } => {
+                self.framebuffer = Some(framebuffer);
+                self.info = Some(info);
+                self.reconfigure = reconfigure;
+                self.rotation = Rotation::Upright;
+                self.updates = 0;
+            }
+            DeviceMessage::Reconfigured { info, framebuffer } => {
+                self.framebuffer = Some(framebuffer);
+                self.info = Some(info);
+                self.updates = 0;
+            }
+            DeviceMessage::Rotated(rotation) => self.rotation = rotation,
+            DeviceMessage::Stats(stats) => self.stats.push(stats),
+            DeviceMessage::Updated { chunks } => {
+                self.chunks.extend(chunks);
+                self.updates += 1;
+            }
+            DeviceMessage::Closed => self.framebuffer = None,
+            _ => {}
+        }
+    }
+
+    /// Ask the kindle for `settings` and wait until it switched.
+    async fn reconfigure(&mut self, settings: Settings) {
+        let reconfigure = self.reconfigure.as_ref().expect("can't reconfigure");
+        reconfigure.send(settings).unwrap();
+
+        while self.info.as_ref().map(Info::settings) != Some(settings) {
+            self.next().await;
+        }
+    }
+
+    async fn connected(&mut self) {
+        self.framebuffer = None;
+
+        while self.framebuffer.is_none() {
+            self.next().await;
+        }
+    }
+
+    /// Wait for the first frame of the current connection.
+    async fn first_frame(&mut self) -> Vec<u8> {
+        while self.framebuffer.is_none() || self.updates == 0 {
+            self.next().await;
+        }
+
+        self.framebuffer.as_ref().unwrap().lock().unwrap().to_vec()
+    }
+
+    /// Put `pixels` on the kindle's screen and wait for the viewer to show
+    /// exactly the same thing.
+    async fn expect(&mut self, screen: &Screen, pixels: &[u8]) {
+        screen.show(pixels);
+
+        let wait = async {
+            // The framebuffer is shared, so it runs ahead of the messages
+            // about it. Only look again once another frame was reported.
+            let mut checked = None;
+
+            loop {
+                if self.updates > 0
+                    && checked != Some(self.updates)
+                    && let Some(framebuffer) = &self.framebuffer
+                    && **framebuffer.lock().unwrap() == *pixels
+                {
+                    return;
+                }
+
+                checked = Some(self.updates);
+                self.next().await;
+            }
+        };
+
+        if time::timeout(TIMEOUT, wait).await.is_err() {
+            let framebuffer = self.framebuffer.as_ref().expect("not connected");
+            let framebuffer = framebuffer.lock().unwrap();
+            let wrong = framebuffer
+                .iter()
+                .zip(pixels)
+                .filter(|(a, b)| a != b)
+                .count();
+
+            panic!("viewer still has {wrong} wrong pixels");
+        }
+    }
+}
+
+impl Drop for Viewer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn options(screen: &Screen, config: &Config) -> Options {
+    let screen = screen.clone();
+
+    Options {
+        source: Some(Box::new(move || Ok(Box::new(screen.clone())))),
+        input: false,
+        config: Some(config.clone()),
+        dir: None,
+    }
+}
+
+/// Keep streaming from the kindle with `kindle_key` into a fresh channel.
+fn spawn_viewer(server: &Endpoint, kindle_key: &SecretKey) -> Viewer {
+    let (sender, messages) = mpsc::unbounded_channel();
+    let task = tokio::spawn(kinshare_server::run_device(
+        server.clone(),
+        kindle_key.public(),
+        sender,
+        Default::default(),
+    ));
+
+    Viewer::new(task, messages)
+}
+
+/// Serve `screen` on `listener` and stream it into a viewer connecting to
+/// `direct`, without iroh on either side.
+fn spawn_direct(
+    screen: &Screen,
+    listener: DirectListener,
+    direct: Direct,
+) -> (JoinHandle<anyhow::Result<()>>, Viewer) {
+    let options = options(screen, &serde_json::from_str(r#"{ "fps": 100 }"#).unwrap());
+    let kindle = tokio::spawn(kinshare_client::serve_direct(listener, options));
+
+    let (sender, messages) = mpsc::unbounded_channel();
+    let task = tokio::spawn(kinshare_server::run(
+        sender,
+        kinshare_server::Options {
+            direct: Some(direct),
+            ..Default::default()
+        },
+    ));
+
+    (kindle, Viewer::new(task, messages))
+}
+
+/// A sequence that touches every chunk at least once: a gradient in the
+/// panel's 16 gray levels, a block drawn over part of it, noise, then a blank
+/// screen again.
+fn frames(width: u32, height: u32) -> Vec<Vec<u8>> {
+    let (width, height) = (width as usize, height as usize);
+
+    let gradient = (0..width * height)
+        .map(|i| ((i % width) * 16 / width) as u8 * 0x11)
+        .collect::<Vec<u8>>();
+
+    let mut block = gradient.clone();
+    for y in height / 3..height * 2 / 3 {
+        block[y * width + width / 5..y * width + width * 3 / 4].fill(0x00);
+    }
+
+    let mut state = 0x2545_f491_u32;
+    let noise = (0..width * height)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect::<Vec<u8>>();
+
+    vec![gradient, block, noise, vec![0xff; width * height]]
+}
+
+async fn round_trip(width: u32, height: u32, config: &str) {
+    let mut harness = Harness::start(Screen::new(width, height), config).await;
+
+    for frame in frames(width, height) {
+        harness.expect(&frame).await;
+    }
+}
+
+#[tokio::test]
+async fn empty_first_frame() {
+    let mut harness = Harness::start(Screen::new(64, 48), r#"{ "fps": 100 }"#).await;
+
+    assert!(
+        harness
+            .viewer
+            .first_frame()
+            .await
+            .iter()
+            .all(|&pixel| pixel == 0)
+    );
+
+    for frame in frames(64, 48) {
+        harness.expect(&frame).await;
+    }
+}
+
+#[tokio::test]
+async fn odd_grid_sizes() {
+    let cases = [
+        // Chunks that differ in size by a pixel both ways.
+        (101, 67, 7, 5, 3),
+        // A single column of chunks, more threads than rows evenly divide.
+        (33, 250, 1, 9, 4),
+        // As many chunks across as coordinates fit in a byte.
+        (300, 7, 256, 7, 2),
+        // More threads than rows of chunks.
+        (64, 64, 3, 2, 5),
+        // One chunk for the whole screen.
+        (17, 13, 1, 1, 1),
+    ];
+
+    for (width, height, chunks_per_x, chunks_per_y, thread_count) in cases {
+        let config = format!(
+            r#"{{
+                "chunks_per_x": {chunks_per_x},
+                "chunks_per_y": {chunks_per_y},
+                "thread_count": {thread_count},
+                "fps": 100
+            }}"#
+        );
+
+        round_trip(width, height, &config).await;
+    }
+}
+
+#[tokio::test]
+async fn every_codec() {
+    for codec in ["lz4", "gray4", "rle", "zstd"] {
+        for delta in [false, true] {
+            let config = format!(
+                r#"{{
+                    "chunks_per_x": 5,
+                    "chunks_per_y": 4,
+                    "codec": "{codec}",
+                    "delta": {delta},
+                    "fps": 100
+                }}"#
+            );
+
+            round_trip(90, 70, &config).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn viewer_reconnects() {
+    let [gradient, block, noise, blank] = frames(80, 60).try_into().unwrap();
+    let mut harness = Harness::start(Screen::new(80, 60), r#"{ "fps": 100 }"#).await;
+
+    harness.expect(&gradient).await;
+    harness.expect(&block).await;
+
+    harness.restart_viewer();
+
+    // The kindle only notices the old connection is gone once it sends
+    // something, the new one has to start from a full frame regardless.
+    harness.expect(&noise).await;
+    harness.expect(&blank).await;
+}
+
+#[tokio::test]
+async fn kindle_restarts() {
+    let [gradient, block, noise, blank] = frames(80, 60).try_into().unwrap();
+    let mut harness = Harness::start(Screen::new(80, 60), r#"{ "fps": 100 }"#).await;
+
+    harness.expect(&gradient).await;
+
+    harness.stop_kindle().await;
+    harness.screen.show(&block);
+    harness.start_kindle().await;
+    harness.viewer.connected().await;
+
+    // Nothing changed on the new connection's first capture, it still has to
+    // arrive in full.
+    assert_eq!(harness.viewer.first_frame().await, block);
+
+    harness.expect(&noise).await;
+    harness.expect(&blank).await;
+}
+
+#[tokio::test]
+async fn plain_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let direct = Direct::Tcp {
+        address: listener.local_addr().unwrap().to_string(),
+    };
+
+    let screen = Screen::new(64, 48);
+    let (kindle, mut viewer) = spawn_direct(&screen, DirectListener::Tcp(listener), direct);
+
+    for frame in frames(64, 48) {
+        viewer.expect(&screen, &frame).await;
+    }
+
+    kindle.abort();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket() {
+    let path = std::env::temp_dir().join(format!("kinshare-test-{}.sock", std::process::id()));
+    let direct = Direct::Unix { path: path.clone() };
+
+    let screen = Screen::new(64, 48);
+    let listener = direct.bind().await.unwrap();
+    let (kindle, mut viewer) = spawn_direct(&screen, listener, direct);
+
+    for frame in frames(64, 48) {
+        viewer.expect(&screen, &frame).await;
+    }
+
+    kindle.abort();
+    std::fs::remove_file(path).ok();
+}
+
+#[tokio::test]
+async fn reconfigures_mid_stream() {
+    let [gradient, block, noise, blank] = frames(90, 70).try_into().unwrap();
+    let mut harness = Harness::start(Screen::new(90, 70), r#"{ "fps": 100 }"#).await;
+
+    harness.expect(&gradient).await;
+
+    let settings = Settings {
+        chunks_per_x: 7,
+        chunks_per_y: 3,
+        thread_count: 3,
+        fps: 50.0,
+    };
+    harness.viewer.reconfigure(settings).await;
+
+    // The new framebuffer starts out blank, so the unchanged screen has to
+    // come over again in full.
+    harness.expect(&gradient).await;
+    harness.expect(&block).await;
+
+    harness
+        .viewer
+        .reconfigure(Settings {
+            chunks_per_x: 1,
+            chunks_per_y: 1,
+            thread_count: 1,
+            fps: 100.0,
+        })
+        .await;
+
+    harness.expect(&noise).await;
+    harness.expect(&blank).await;
+}
+
+#[tokio::test]
+async fn follows_the_panel_when_it_rotates() {
+    let [gradient, block, _, _] = frames(90, 70).try_into().unwrap();
+    let mut harness = Harness::start(Screen::new(90, 70), r#"{ "fps": 100 }"#).await;
+
+    harness.expect(&gradient).await;
+    harness.expect(&block).await;
+
+    harness.screen.rotate();
+
+    // Same pixel count, so only a capture in the new geometry matches.
+    let [gradient, block, _, _] = frames(70, 90).try_into().unwrap();
+    harness.expect(&gradient).await;
+    harness.expect(&block).await;
+
+    let info = harness.viewer.info.as_ref().unwrap();
+    assert_eq!((info.display_width, info.display_height), (70, 90));
+    // Already upright as captured, turning it again would show it sideways.
+    assert_eq!(harness.viewer.rotation, Rotation::Upright);
+}
+
+#[tokio::test]
+async fn idles_while_nothing_changes() {
+    let [gradient, block, _, _] = frames(64, 48).try_into().unwrap();
+    let config = r#"{ "fps": 100, "idle_fps": 4, "idle_after": 10 }"#;
+    let mut harness = Harness::start(Screen::new(64, 48), config).await;
+
+    harness.expect(&gradient).await;
+
+    // Long enough to settle into the idle rate, then count captures over a
+    // second of it. A fixed rate would make around a hundred.
+    time::sleep(Duration::from_millis(500)).await;
+
+    let captures = &harness.screen.captures;
+    let before = captures.load(Ordering::Relaxed);
+    time::sleep(Duration::from_secs(1)).await;
+    let idle = captures.load(Ordering::Relaxed) - before;
+
+    assert!(idle <= 20, "captured {idle} frames while idle");
+
+    // A change still gets picked up on the next idle tick.
+    time::timeout(Duration::from_secs(2), harness.expect(&block))
+        .await
+        .expect("change took too long to show up");
+}
+
+#[tokio::test]
+async fn reports_frame_stats() {
+    let gradient = frames(64, 48).swap_remove(0);
+    let config = r#"{ "chunks_per_x": 8, "chunks_per_y": 6, "fps": 100 }"#;
+    let mut harness = Harness::start(Screen::new(64, 48), config).await;
+
+    harness.viewer.first_frame().await;
+    harness.viewer.stats.clear();
+    harness.expect(&gradient).await;
+
+    // The gradient touches every chunk, each of which is sent exactly once
+    // however the frames happened to split them up.
+    let stats = &harness.viewer.stats;
+    assert_eq!(stats.iter().map(|frame| frame.chunks).sum::<usize>(), 48);
+    assert_eq!(
+        stats.iter().map(|frame| frame.pixels).sum::<usize>(),
+        64 * 48
+    );
+    assert!(stats.iter().all(|frame| frame.bytes > 0));
+    assert!(stats.iter().all(|frame| frame.interval.is_some()));
+
+    let link = stats.last().unwrap().link.expect("no link stats over iroh");
+    assert!(!link.relayed);
+}
+
+#[tokio::test]
+async fn reports_updated_chunks() {
+    let [gradient, block, _, _] = frames(64, 48).try_into().unwrap();
+    let config = r#"{ "chunks_per_x": 8, "chunks_per_y": 6, "fps": 100 }"#;
+    let mut harness = Harness::start(Screen::new(64, 48), config).await;
+
+    harness.expect(&gradient).await;
+    harness.viewer.chunks.clear();
+    harness.expect(&block).await;
+
+    // The block covers columns 12 to 47 and rows 16 to 31, which with 8x8
+    // chunks is the middle five of rows 2 and 3.
+    let mut chunks = harness.viewer.chunks.clone();
+    chunks.sort_unstable();
+
+    let expected = (1..=5)
+        .flat_map(|x| (2..=3).map(move |y| (x, y)))
+        .collect::<Vec<(u8, u8)>>();
+    assert_eq!(chunks, expected);
+}
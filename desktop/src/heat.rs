@@ -0,0 +1,58 @@
+use std::time::{Duration, Instant};
+
+/// How long it takes a chunk to cool down to half its heat.
+const HALF_LIFE: Duration = Duration::from_millis(400);
+
+/// How recently and how often each chunk of a stream changed, for the chunk
+/// overlay. Every update adds one to a chunk's heat, which then halves every
+/// [`HALF_LIFE`], so a single change flashes and fades while a chunk that
+/// keeps changing stays lit.
+#[derive(Debug)]
+pub struct ChunkHeat {
+    chunks_per_x: usize,
+    /// Heat as of the instant next to it, row by row.
+    chunks: Vec<(f32, Instant)>,
+}
+
+impl ChunkHeat {
+    pub fn new(chunks_per_x: usize, chunks_per_y: usize) -> Self {
+        Self {
+            chunks_per_x,
+            chunks: vec![(0.0, Instant::now()); chunks_per_x * chunks_per_y],
+        }
+    }
+
+    /// Heat up the chunks at these grid coordinates. Ones outside the grid
+    /// are ignored.
+    pub fn touch(&mut self, chunks: &[(u8, u8)]) {
+        let now = Instant::now();
+
+        for &(x, y) in chunks {
+            if x as usize >= self.chunks_per_x {
+                continue;
+            }
+
+            if let Some(chunk) = self
+                .chunks
+                .get_mut(y as usize * self.chunks_per_x + x as usize)
+            {
+                *chunk = (decayed(*chunk, now) + 1.0, now);
+            }
+        }
+    }
+
+    /// One byte per chunk for the overlay texture, 0 for cold and approaching
+    /// 255 the hotter a chunk is.
+    pub fn intensities(&self) -> Vec<u8> {
+        let now = Instant::now();
+
+        self.chunks
+            .iter()
+            .map(|&chunk| ((1.0 - (-decayed(chunk, now)).exp()) * 255.0) as u8)
+            .collect()
+    }
+}
+
+fn decayed((heat, at): (f32, Instant), now: Instant) -> f32 {
+    heat * 0.5_f32.powf((now - at).as_secs_f32() / HALF_LIFE.as_secs_f32())
+}
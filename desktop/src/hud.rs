@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use iced::widget::{column, container, text};
+use iced::{Element, Font};
+
+use kinshare_server::FrameStats;
+
+/// Frames the averages are taken over.
+const WINDOW: usize = 60;
+
+/// Stats of the last few frames of a stream, for the overlay.
+#[derive(Debug, Default)]
+pub struct StatsWindow {
+    frames: VecDeque<FrameStats>,
+}
+
+impl StatsWindow {
+    pub fn push(&mut self, stats: FrameStats) {
+        if self.frames.len() == WINDOW {
+            self.frames.pop_front();
+        }
+
+        self.frames.push_back(stats);
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub fn view<'a, Message: 'a>(&self) -> Element<'a, Message> {
+        let lines = match self.frames.back() {
+            Some(last) => self.lines(last),
+            None => vec!["Waiting for a frame".to_owned()],
+        };
+
+        container(column(
+            lines
+                .into_iter()
+                .map(|line| text(line).font(Font::MONOSPACE).size(12.0).into()),
+        ))
+        .padding(6.0)
+        .style(container::dark)
+        .into()
+    }
+
+    fn lines(&self, last: &FrameStats) -> Vec<String> {
+        let count = self.frames.len() as f64;
+        let sum =
+            |field: fn(&FrameStats) -> usize| self.frames.iter().map(field).sum::<usize>() as f64;
+
+        let intervals = self
+            .frames
+            .iter()
+            .filter_map(|frame| frame.interval)
+            .collect::<Vec<Duration>>();
+
+        let mut lines = Vec::new();
+
+        let interval = (!intervals.is_empty())
+            .then(|| intervals.iter().sum::<Duration>() / intervals.len() as u32)
+            // Replayed and seeked frames can all come in at once.
+            .filter(|interval| !interval.is_zero());
+
+        lines.push(match interval {
+            Some(interval) => format!(
+                "{:.1} fps, {:.1} ms apart",
+                1.0 / interval.as_secs_f64(),
+                interval.as_secs_f64() * 1000.0
+            ),
+            None => "-- fps".to_owned(),
+        });
+
+        let (pixels, bytes) = (sum(|frame| frame.pixels), sum(|frame| frame.bytes));
+
+        lines.push(format!(
+            "{:.1} chunks/frame, last {}",
+            sum(|frame| frame.chunks) / count,
+            last.chunks
+        ));
+        lines.push(format!(
+            "{:.1} KiB/frame, {:.1}:1",
+            bytes / count / 1024.0,
+            if bytes > 0.0 { pixels / bytes } else { 0.0 }
+        ));
+
+        let decode_time = self
+            .frames
+            .iter()
+            .map(|frame| frame.decode_time)
+            .sum::<Duration>()
+            / self.frames.len() as u32;
+
+        lines.push(format!(
+            "decode {:.2} ms",
+            decode_time.as_secs_f64() * 1000.0
+        ));
+
+        if let Some(link) = last.link {
+            lines.push(format!(
+                "rtt {:.1} ms, {}",
+                link.rtt.as_secs_f64() * 1000.0,
+                if link.relayed { "relayed" } else { "direct" }
+            ));
+        }
+
+        lines
+    }
+}
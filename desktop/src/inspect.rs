@@ -0,0 +1,107 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use iced::widget::{column, container, text};
+use iced::{Element, Font};
+
+use kinshare_shared::messages::Info;
+
+/// What dragging over a view with the left button does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tool {
+    /// Touch the kindle, if it takes input.
+    #[default]
+    Touch,
+    /// Pick the region screenshots are cropped to, and copy its bounds.
+    Select,
+    /// Measure between two panel pixels.
+    Measure,
+}
+
+impl Tool {
+    pub const ALL: [Self; 3] = [Self::Touch, Self::Select, Self::Measure];
+}
+
+impl fmt::Display for Tool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Touch => "Touch",
+            Self::Select => "Select region",
+            Self::Measure => "Ruler",
+        })
+    }
+}
+
+/// A line measured with [`Tool::Measure`], in framebuffer pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ruler {
+    pub from: (u16, u16),
+    pub to: (u16, u16),
+}
+
+impl Ruler {
+    /// Pixels covered along each axis, counting both ends.
+    pub fn extent(&self) -> (u16, u16) {
+        (
+            self.from.0.abs_diff(self.to.0) + 1,
+            self.from.1.abs_diff(self.to.1) + 1,
+        )
+    }
+
+    /// Distance between the centers of the end pixels.
+    pub fn length(&self) -> f64 {
+        let dx = f64::from(self.from.0) - f64::from(self.to.0);
+        let dy = f64::from(self.from.1) - f64::from(self.to.1);
+
+        dx.hypot(dy)
+    }
+}
+
+/// The pixel under the cursor and the ruler, for the overlay. `None` when
+/// there's nothing to show.
+pub fn readout<'a, Message: 'a>(
+    info: &Info,
+    framebuffer: &Mutex<Box<[u8]>>,
+    hover: Option<(u16, u16)>,
+    ruler: Option<Ruler>,
+) -> Option<Element<'a, Message>> {
+    let mut lines = Vec::new();
+
+    // A reconnect may have shrunk the framebuffer since the cursor moved.
+    if let Some((x, y)) = hover
+        && let Some(&gray) = framebuffer
+            .lock()
+            .unwrap()
+            .get(y as usize * info.display_width + x as usize)
+    {
+        lines.push(format!("{x}, {y}: gray {gray} (0x{gray:02x})"));
+    }
+
+    if let Some(ruler) = ruler {
+        let (width, height) = ruler.extent();
+
+        lines.push(format!(
+            "{}, {} to {}, {}: {width}x{height} px, {:.1} px long",
+            ruler.from.0,
+            ruler.from.1,
+            ruler.to.0,
+            ruler.to.1,
+            ruler.length()
+        ));
+    }
+
+    if lines.is_empty() {
+        return None;
+    }
+
+    Some(
+        container(column(
+            lines
+                .into_iter()
+                .map(|line| text(line).font(Font::MONOSPACE).size(12.0).into()),
+        ))
+        .padding(6.0)
+        .style(container::dark)
+        .into(),
+    )
+}
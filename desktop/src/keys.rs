@@ -0,0 +1,71 @@
+use iced::keyboard::key::Code;
+
+/// Map a physical key to the linux `KEY_*` code the kindle expects. Only keys
+/// that make sense on an e-reader are forwarded.
+pub fn linux_code(code: Code) -> Option<u16> {
+    Some(match code {
+        Code::Escape => 1,
+        Code::Digit1 => 2,
+        Code::Digit2 => 3,
+        Code::Digit3 => 4,
+        Code::Digit4 => 5,
+        Code::Digit5 => 6,
+        Code::Digit6 => 7,
+        Code::Digit7 => 8,
+        Code::Digit8 => 9,
+        Code::Digit9 => 10,
+        Code::Digit0 => 11,
+        Code::Minus => 12,
+        Code::Equal => 13,
+        Code::Backspace => 14,
+        Code::Tab => 15,
+        Code::KeyQ => 16,
+        Code::KeyW => 17,
+        Code::KeyE => 18,
+        Code::KeyR => 19,
+        Code::KeyT => 20,
+        Code::KeyY => 21,
+        Code::KeyU => 22,
+        Code::KeyI => 23,
+        Code::KeyO => 24,
+        Code::KeyP => 25,
+        Code::BracketLeft => 26,
+        Code::BracketRight => 27,
+        Code::Enter => 28,
+        Code::KeyA => 30,
+        Code::KeyS => 31,
+        Code::KeyD => 32,
+        Code::KeyF => 33,
+        Code::KeyG => 34,
+        Code::KeyH => 35,
+        Code::KeyJ => 36,
+        Code::KeyK => 37,
+        Code::KeyL => 38,
+        Code::Semicolon => 39,
+        Code::Quote => 40,
+        Code::Backquote => 41,
+        Code::ShiftLeft => 42,
+        Code::Backslash => 43,
+        Code::KeyZ => 44,
+        Code::KeyX => 45,
+        Code::KeyC => 46,
+        Code::KeyV => 47,
+        Code::KeyB => 48,
+        Code::KeyN => 49,
+        Code::KeyM => 50,
+        Code::Comma => 51,
+        Code::Period => 52,
+        Code::Slash => 53,
+        Code::ShiftRight => 54,
+        Code::Space => 57,
+        Code::Home => 102,
+        Code::ArrowUp => 103,
+        // Page turn buttons on kindles that have them
+        Code::PageUp => 104,
+        Code::ArrowLeft => 105,
+        Code::ArrowRight => 106,
+        Code::ArrowDown => 108,
+        Code::PageDown => 109,
+        _ => return None,
+    })
+}
@@ -1,90 +1,935 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use iced::futures::SinkExt;
+use iced::keyboard::{self, key};
 use iced::wgpu::util::DeviceExt;
-use iced::widget::{Column, Stack, center, shader, text};
-use iced::{Alignment, Element, Length, Subscription, Theme, wgpu};
+use iced::widget::{
+    Column, Stack, button, canvas, center, checkbox, column, container, pick_list, row, scrollable,
+    shader, slider, space, text, text_input,
+};
+use iced::{
+    Alignment, Element, Event, Length, Point, Rectangle, Subscription, Task, Theme, Vector, mouse,
+    wgpu,
+};
 
-use kinshare_shared::messages::Info;
+use kinshare_server::{
+    Command, DeviceId, DeviceMessage, DirtyChunks, ImageFormat, ReplayStatus, Screenshot,
+};
+use kinshare_shared::messages::{Info, InputEvent, Rect, Rotation, Settings, TouchPhase};
+use kinshare_shared::transport::Direct;
 use tokio::sync::mpsc;
 
+use crate::heat::ChunkHeat;
+use crate::hud::StatsWindow;
+use crate::inspect::{Ruler, Tool};
+use crate::software::SoftwareImage;
+
+mod heat;
+mod hud;
+mod inspect;
+mod keys;
+mod software;
+
+const REPLAY_SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+/// Where the frames come from, picked on the command line.
+#[derive(Debug, Clone, Hash)]
+enum Mode {
+    /// Stream from the paired kindles, or the one at `direct`, optionally
+    /// recording every stream.
+    Live {
+        record_dir: Option<PathBuf>,
+        direct: Option<Direct>,
+    },
+    /// Play back a recording made with `--record`.
+    Replay(PathBuf),
+}
+
+impl Mode {
+    fn from_args() -> Result<Self, String> {
+        let mut args = std::env::args().skip(1);
+        let mut record_dir = None;
+        let mut direct = None;
+        let mut replay = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+
+            match arg.as_str() {
+                "--record" => record_dir = Some(value()?.into()),
+                "--connect" => {
+                    let value = value()?;
+                    direct = Some(Direct::parse(&value).ok_or(format!(
+                        "'{value}' isn't an address, expected tcp:<host[:port]> or unix:<path>"
+                    ))?);
+                }
+                "--replay" => replay = Some(value()?.into()),
+                _ => return Err(format!("unknown argument '{arg}'")),
+            }
+        }
+
+        match replay {
+            Some(_) if record_dir.is_some() || direct.is_some() => {
+                Err("--replay can't be combined with other options".to_owned())
+            }
+            Some(path) => Ok(Self::Replay(path)),
+            None => Ok(Self::Live { record_dir, direct }),
+        }
+    }
+}
+
+/// Keys for the kindle, unless a widget like a text input took them.
+fn key_message(
+    event: Event,
+    status: iced::event::Status,
+    _window: iced::window::Id,
+) -> Option<Message> {
+    if status == iced::event::Status::Captured {
+        return None;
+    }
+
+    let (code, pressed) = match event {
+        Event::Keyboard(keyboard::Event::KeyPressed {
+            physical_key: key::Physical::Code(code),
+            ..
+        }) => (code, true),
+        Event::Keyboard(keyboard::Event::KeyReleased {
+            physical_key: key::Physical::Code(code),
+            ..
+        }) => (code, false),
+        _ => return None,
+    };
+
+    Some(Message::Key {
+        code: keys::linux_code(code)?,
+        pressed,
+    })
+}
+
 pub fn main() -> iced::Result {
-    iced::application(State::default, State::update, State::view)
-        .subscription(State::subscription)
-        .title(State::title)
-        .theme(State::theme)
-        .run()
+    let mode = match Mode::from_args() {
+        Ok(mode) => mode,
+        Err(err) => {
+            eprintln!(
+                "{err}\nusage: kinshare-desktop [--record <dir>] [--connect <address>] | --replay <file>"
+            );
+            std::process::exit(2);
+        }
+    };
+
+    let software = software::required();
+
+    iced::application(
+        move || State::new(mode.clone(), software),
+        State::update,
+        State::view,
+    )
+    .subscription(State::subscription)
+    .title(State::title)
+    .theme(State::theme)
+    .run()
 }
 
 struct State {
-    messages: Vec<&'static str>,
+    mode: Mode,
+    /// Draw views on the CPU, there's no GPU to run the shader on.
+    software: bool,
+    messages: Vec<String>,
+    devices: Vec<Device>,
+    selected: Option<DeviceId>,
+    grid: bool,
+    /// Overlay stream stats on every view.
+    hud: bool,
+    /// Draw the chunk grid over every view and tint the chunks that changed
+    /// lately.
+    chunk_overlay: bool,
+    filter: Filter,
+    sharpen: bool,
+    /// Show panels pixel for pixel and scroll around them, instead of fitting
+    /// them to the window.
+    native_size: bool,
+    commands: Option<mpsc::UnboundedSender<Command>>,
+    /// The pairing code being typed in, while the pairing form is open.
+    pairing_code: Option<String>,
+    /// Outcome of the last pairing attempt or screenshot.
+    notice: Option<String>,
+    replay: Option<ReplayStatus>,
+    tool: Tool,
+    /// Show the panel pixel under the cursor and its gray value.
+    inspecting: bool,
+    /// The kindle whose view the cursor is over, which keys go to.
+    pointer_over: Option<DeviceId>,
+    screenshot_format: ImageFormat,
+    /// New stream settings for the selected kindle, while the form is open.
+    settings_form: Option<SettingsForm>,
+}
+
+/// Stream settings as typed in, one string per field.
+#[derive(Debug, Clone)]
+struct SettingsForm {
+    chunks_per_x: String,
+    chunks_per_y: String,
+    thread_count: String,
+    fps: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SettingsField {
+    ChunksPerX,
+    ChunksPerY,
+    ThreadCount,
+    Fps,
+}
+
+impl SettingsForm {
+    fn new(settings: Settings) -> Self {
+        Self {
+            chunks_per_x: settings.chunks_per_x.to_string(),
+            chunks_per_y: settings.chunks_per_y.to_string(),
+            thread_count: settings.thread_count.to_string(),
+            fps: settings.fps.to_string(),
+        }
+    }
+
+    fn field(&mut self, field: SettingsField) -> &mut String {
+        match field {
+            SettingsField::ChunksPerX => &mut self.chunks_per_x,
+            SettingsField::ChunksPerY => &mut self.chunks_per_y,
+            SettingsField::ThreadCount => &mut self.thread_count,
+            SettingsField::Fps => &mut self.fps,
+        }
+    }
+
+    /// What's typed in, once every field parses and the kindle would accept
+    /// it for `info`'s display.
+    fn settings(&self, info: &Info) -> Option<Settings> {
+        let settings = Settings {
+            chunks_per_x: self.chunks_per_x.trim().parse().ok()?,
+            chunks_per_y: self.chunks_per_y.trim().parse().ok()?,
+            thread_count: self.thread_count.trim().parse().ok()?,
+            fps: self.fps.trim().parse().ok()?,
+        };
+
+        info.with_settings(settings).validate().ok()?;
+
+        Some(settings)
+    }
+}
+
+struct Device {
+    id: DeviceId,
+    status: String,
     stream: Option<Arc<StreamState>>,
+    /// Region screenshots are cropped to, in framebuffer pixels.
+    selection: Option<Rect>,
+    /// Framebuffer pixel under the cursor, while inspecting.
+    hover: Option<(u16, u16)>,
+    ruler: Option<Ruler>,
+    stats: StatsWindow,
 }
 
 #[derive(Debug)]
 struct StreamState {
+    device: DeviceId,
     info: Info,
-    updated: AtomicBool,
+    /// Chunks to upload before the next draw.
+    dirty: Mutex<DirtyChunks>,
+    rotation: AtomicU8,
     framebuffer: Arc<Mutex<Box<[u8]>>>,
+    heat: Mutex<ChunkHeat>,
+    /// Only used when drawing without a GPU.
+    software: Mutex<SoftwareImage>,
+    /// Of the window the view was last drawn in, as `f32` bits.
+    scale_factor: AtomicU32,
+    input: Option<mpsc::UnboundedSender<InputEvent>>,
+    reconfigure: Option<mpsc::UnboundedSender<Settings>>,
+}
+
+impl StreamState {
+    fn rotation(&self) -> Rotation {
+        Rotation::from_raw(self.rotation.load(Ordering::Relaxed) as u32)
+    }
+
+    fn scale_factor(&self) -> f32 {
+        f32::from_bits(self.scale_factor.load(Ordering::Relaxed))
+    }
+
+    /// Width and height of the panel the way it's shown, upright.
+    fn shown_size(&self) -> (usize, usize) {
+        if self.rotation().is_sideways() {
+            (self.info.display_height, self.info.display_width)
+        } else {
+            (self.info.display_width, self.info.display_height)
+        }
+    }
+
+    /// Inverse of the letterboxing and `view` in `shader.wgsl`, from a point
+    /// in `bounds` to a framebuffer pixel. Points on the border are `None`
+    /// unless `clamp` is set, in which case they snap to the nearest edge.
+    fn panel_position(
+        &self,
+        bounds: Rectangle,
+        view: ViewTransform,
+        position: Point,
+        clamp: bool,
+    ) -> Option<(u16, u16)> {
+        let (width, height) = (
+            self.info.display_width as f32,
+            self.info.display_height as f32,
+        );
+        let rotation = self.rotation();
+
+        let Point { x: mut u, y: mut v } = view.to_panel(self.fit_position(bounds, position));
+
+        if clamp {
+            u = u.clamp(0.0, 1.0);
+            v = v.clamp(0.0, 1.0);
+        } else if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+
+        let (u, v) = match rotation {
+            Rotation::Upright => (u, v),
+            Rotation::Clockwise => (v, 1.0 - u),
+            Rotation::UpsideDown => (1.0 - u, 1.0 - v),
+            Rotation::CounterClockwise => (1.0 - v, u),
+        };
+
+        Some((
+            (u * width).min(width - 1.0) as u16,
+            (v * height).min(height - 1.0) as u16,
+        ))
+    }
+
+    /// Where `position` falls on the panel fitted into `bounds`, 0 to 1 on
+    /// both axes of the upright panel and beyond that on the border.
+    fn fit_position(&self, bounds: Rectangle, position: Point) -> Point {
+        let (shown_width, shown_height) = self.shown_size();
+
+        let screen_aspect_ratio = shown_width as f32 / shown_height as f32;
+        let display_aspect_ratio = bounds.width / bounds.height;
+
+        let mut u = (position.x - bounds.x) / bounds.width - 0.5;
+        let mut v = (position.y - bounds.y) / bounds.height - 0.5;
+
+        if screen_aspect_ratio < display_aspect_ratio {
+            u /= screen_aspect_ratio / display_aspect_ratio;
+        } else {
+            v /= display_aspect_ratio / screen_aspect_ratio;
+        }
+
+        Point::new(u + 0.5, v + 0.5)
+    }
 }
 
 #[derive(Debug, Clone)]
 enum Message {
     Server(kinshare_server::Message),
+    Input(DeviceId, InputEvent),
+    /// A key no other widget took, for the kindle under the cursor.
+    Key {
+        code: u16,
+        pressed: bool,
+    },
+    /// The cursor went over or left a kindle's view.
+    PointerOver(DeviceId, bool),
+    Select(DeviceId),
+    ToggleGrid(bool),
+    ToggleHud(bool),
+    ToggleChunkOverlay(bool),
+    Filter(Filter),
+    ToggleSharpen(bool),
+    ToggleNativeSize(bool),
+    /// Nothing to do but redraw, so the chunk overlay fades.
+    Redraw,
+    StartPairing,
+    PairingCodeChanged(String),
+    SubmitPairing,
+    CancelPairing,
+    /// Replay controls, forwarded to the server as-is.
+    Replay(Command),
+    Tool(Tool),
+    ToggleInspecting(bool),
+    Hover(DeviceId, Option<(u16, u16)>),
+    Selection(DeviceId, Option<Rect>),
+    /// A selection was made, put its bounds on the clipboard.
+    CopySelection(DeviceId),
+    Ruler(DeviceId, Option<Ruler>),
+    ScreenshotFormat(ImageFormat),
+    SaveScreenshot,
+    EditSettings,
+    SettingsChanged(SettingsField, String),
+    ApplySettings,
+    CancelSettings,
 }
 
 impl State {
-    fn default() -> Self {
+    fn new(mode: Mode, software: bool) -> Self {
         Self {
-            messages: vec!["Initializing..."],
-            stream: None,
+            mode,
+            software,
+            messages: vec!["Initializing...".to_owned()],
+            devices: Vec::new(),
+            selected: None,
+            grid: false,
+            hud: false,
+            chunk_overlay: false,
+            filter: Filter::Bilinear,
+            sharpen: false,
+            native_size: false,
+            commands: None,
+            pairing_code: None,
+            notice: None,
+            replay: None,
+            tool: Tool::Touch,
+            inspecting: false,
+            pointer_over: None,
+            screenshot_format: ImageFormat::Png,
+            settings_form: None,
         }
     }
 
-    fn update(&mut self, Message::Server(server): Message) {
-        match server {
-            kinshare_server::Message::Message(message) => self.messages.push(message),
-            kinshare_server::Message::Connected { info, framebuffer } => {
-                self.stream = Some(Arc::new(StreamState {
-                    info,
-                    updated: AtomicBool::new(true),
-                    framebuffer,
-                }));
+    fn update(&mut self, message: Message) -> Task<Message> {
+        match message {
+            Message::Server(kinshare_server::Message::Message(message)) => {
+                self.messages.push(message.to_owned());
             }
-            kinshare_server::Message::Updated => {
-                let Some(stream) = &self.stream else {
-                    return;
+            Message::Server(kinshare_server::Message::Ready(commands)) => {
+                self.commands = Some(commands);
+            }
+            Message::Server(kinshare_server::Message::Paired(id)) => {
+                self.notice = Some(format!("Paired with {}", id.fmt_short()));
+
+                if self.device(id).is_none() {
+                    self.devices.push(Device {
+                        id,
+                        status: String::new(),
+                        stream: None,
+                        selection: None,
+                        hover: None,
+                        ruler: None,
+                        stats: StatsWindow::default(),
+                    });
+                }
+
+                self.selected = Some(id);
+            }
+            Message::Server(kinshare_server::Message::PairingFailed(err)) => {
+                self.notice = Some(format!("Pairing failed: {err}"));
+            }
+            Message::Server(kinshare_server::Message::Replay(status)) => {
+                self.replay = Some(status);
+            }
+            Message::Server(kinshare_server::Message::Devices(ids)) => {
+                self.selected = ids.first().copied();
+                self.devices = ids
+                    .into_iter()
+                    .map(|id| Device {
+                        id,
+                        status: String::new(),
+                        stream: None,
+                        selection: None,
+                        hover: None,
+                        ruler: None,
+                        stats: StatsWindow::default(),
+                    })
+                    .collect();
+            }
+            Message::Server(kinshare_server::Message::Device(id, message)) => {
+                if let Some(device) = self.devices.iter_mut().find(|device| device.id == id) {
+                    device.update(message);
+                }
+            }
+            Message::Input(id, event) => self.send_input(id, event),
+            Message::Key { code, pressed } => {
+                if let Some(id) = self.pointer_over {
+                    self.send_input(id, InputEvent::Key { code, pressed });
+                }
+            }
+            Message::PointerOver(id, over) => {
+                if over {
+                    self.pointer_over = Some(id);
+                } else if self.pointer_over == Some(id) {
+                    self.pointer_over = None;
+                }
+            }
+            Message::Select(id) => {
+                self.selected = Some(id);
+                self.pointer_over = None;
+                self.settings_form = None;
+            }
+            Message::ToggleGrid(grid) => {
+                self.grid = grid;
+                // Views come and go, the next move says which one is under
+                // the cursor.
+                self.pointer_over = None;
+            }
+            Message::ToggleHud(hud) => self.hud = hud,
+            Message::ToggleChunkOverlay(chunk_overlay) => self.chunk_overlay = chunk_overlay,
+            Message::Redraw => {}
+            Message::Filter(filter) => self.filter = filter,
+            Message::ToggleSharpen(sharpen) => self.sharpen = sharpen,
+            Message::ToggleNativeSize(native_size) => self.native_size = native_size,
+            Message::StartPairing => {
+                self.pairing_code = Some(String::new());
+                self.notice = None;
+            }
+            Message::PairingCodeChanged(code) => self.pairing_code = Some(code),
+            Message::SubmitPairing => {
+                let (Some(code), Some(commands)) = (self.pairing_code(), &self.commands) else {
+                    return Task::none();
+                };
+
+                if commands.send(Command::Pair { code }).is_ok() {
+                    self.pairing_code = None;
+                    self.notice = Some("Looking for the kindle...".to_owned());
+                }
+            }
+            Message::CancelPairing => self.pairing_code = None,
+            Message::Replay(command) => {
+                if let Some(commands) = &self.commands {
+                    commands.send(command).ok();
+                }
+            }
+            Message::Tool(tool) => {
+                self.tool = tool;
+
+                for device in &mut self.devices {
+                    device.ruler = None;
+                }
+            }
+            Message::ToggleInspecting(inspecting) => {
+                self.inspecting = inspecting;
+
+                for device in &mut self.devices {
+                    device.hover = None;
+                }
+            }
+            Message::Hover(id, hover) => {
+                if let Some(device) = self.devices.iter_mut().find(|device| device.id == id) {
+                    device.hover = hover;
+                }
+            }
+            Message::Selection(id, selection) => {
+                if let Some(device) = self.devices.iter_mut().find(|device| device.id == id) {
+                    device.selection = selection;
+                }
+            }
+            Message::CopySelection(id) => {
+                if let Some(selection) = self.device(id).and_then(|device| device.selection) {
+                    let bounds = format!(
+                        "{},{},{},{}",
+                        selection.x, selection.y, selection.width, selection.height
+                    );
+
+                    self.notice = Some(format!("Copied {bounds}"));
+
+                    return iced::clipboard::write(bounds);
+                }
+            }
+            Message::Ruler(id, ruler) => {
+                if let Some(device) = self.devices.iter_mut().find(|device| device.id == id) {
+                    device.ruler = ruler;
+                }
+            }
+            Message::ScreenshotFormat(format) => self.screenshot_format = format,
+            Message::SaveScreenshot => {
+                self.notice = Some(match self.save_screenshot() {
+                    Ok(path) => format!("Saved {}", path.display()),
+                    Err(err) => format!("Screenshot failed: {err:#}"),
+                });
+            }
+            Message::EditSettings => {
+                self.settings_form = self
+                    .selected_stream()
+                    .map(|stream| SettingsForm::new(stream.info.settings()));
+            }
+            Message::SettingsChanged(field, value) => {
+                if let Some(form) = &mut self.settings_form {
+                    *form.field(field) = value;
+                }
+            }
+            Message::ApplySettings => {
+                let (Some(form), Some(stream)) = (&self.settings_form, self.selected_stream())
+                else {
+                    return Task::none();
                 };
 
-                stream.updated.store(true, Ordering::Relaxed);
+                if let (Some(settings), Some(reconfigure)) =
+                    (form.settings(&stream.info), &stream.reconfigure)
+                    && reconfigure.send(settings).is_ok()
+                {
+                    self.settings_form = None;
+                }
             }
-            kinshare_server::Message::Closed => self.stream = None,
+            Message::CancelSettings => self.settings_form = None,
+        }
+
+        Task::none()
+    }
+
+    fn send_input(&self, id: DeviceId, event: InputEvent) {
+        if let Some(input) = self
+            .device(id)
+            .and_then(|device| device.stream.as_ref())
+            .and_then(|stream| stream.input.as_ref())
+        {
+            input.send(event).ok();
         }
     }
 
+    fn selected_stream(&self) -> Option<&Arc<StreamState>> {
+        self.selected
+            .and_then(|id| self.device(id))
+            .and_then(|device| device.stream.as_ref())
+    }
+
+    /// Write the selected kindle's screen, cropped to its selection, to the
+    /// working directory.
+    fn save_screenshot(&self) -> anyhow::Result<PathBuf> {
+        let device = self
+            .selected
+            .and_then(|id| self.device(id))
+            .ok_or_else(|| anyhow::anyhow!("no kindle selected"))?;
+        let stream = device
+            .stream
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("kindle isn't streaming"))?;
+
+        let screenshot = Screenshot::capture(
+            &stream.info,
+            &stream.framebuffer.lock().unwrap(),
+            stream.rotation(),
+            device.selection,
+        )?;
+
+        // Milliseconds, so screenshots taken in quick succession all stay.
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = PathBuf::from(format!(
+            "kinshare-{}-{timestamp}.{}",
+            device.id.fmt_short(),
+            self.screenshot_format.extension()
+        ));
+
+        screenshot.save(&path, self.screenshot_format)?;
+
+        Ok(path)
+    }
+
+    /// The code typed into the pairing form, once it looks like the six
+    /// digits the kindle shows.
+    fn pairing_code(&self) -> Option<u32> {
+        let code = self
+            .pairing_code
+            .as_deref()?
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>();
+
+        (code.len() == 6).then(|| code.parse().ok()).flatten()
+    }
+
+    fn device(&self, id: DeviceId) -> Option<&Device> {
+        self.devices.iter().find(|device| device.id == id)
+    }
+
     fn view(&self) -> Element<'_, Message> {
+        let mut toolbar = row![].spacing(4.0).padding(4.0).align_y(Alignment::Center);
+
+        if self.devices.len() > 1 {
+            toolbar = toolbar.extend(self.devices.iter().map(|device| {
+                let selected = !self.grid && self.selected == Some(device.id);
+
+                button(text!("{}", device.id.fmt_short()))
+                    .style(if selected {
+                        button::primary
+                    } else {
+                        button::secondary
+                    })
+                    .on_press(Message::Select(device.id))
+                    .into()
+            }));
+        }
+
+        toolbar = toolbar.push(space::horizontal());
+
+        if let Some(status) = &self.replay {
+            toolbar = toolbar.push(replay_controls(status));
+        }
+
+        if let Some(status) = &self.notice {
+            toolbar = toolbar.push(text!("{}", status));
+        }
+
+        let selected = self.selected.and_then(|id| self.device(id));
+
+        toolbar = toolbar
+            .push(pick_list(Tool::ALL, Some(self.tool), Message::Tool))
+            .push(
+                checkbox(self.inspecting)
+                    .label("Inspect")
+                    .on_toggle(Message::ToggleInspecting),
+            );
+
+        if let Some(device) = selected.filter(|device| device.selection.is_some()) {
+            toolbar = toolbar.push(
+                button("Clear selection")
+                    .style(button::secondary)
+                    .on_press(Message::Selection(device.id, None)),
+            );
+        }
+
+        toolbar = toolbar
+            .push(pick_list(
+                ImageFormat::ALL,
+                Some(self.screenshot_format),
+                Message::ScreenshotFormat,
+            ))
+            .push(
+                button("Save screenshot")
+                    .style(button::secondary)
+                    .on_press_maybe(
+                        selected
+                            .is_some_and(|device| device.stream.is_some())
+                            .then_some(Message::SaveScreenshot),
+                    ),
+            );
+
+        toolbar = match (&self.settings_form, self.selected_stream()) {
+            (Some(form), Some(stream)) => {
+                let field = |placeholder, value, field, width| {
+                    text_input(placeholder, value)
+                        .on_input(move |value| Message::SettingsChanged(field, value))
+                        .on_submit(Message::ApplySettings)
+                        .width(width)
+                };
+
+                toolbar
+                    .push(text("Chunks"))
+                    .push(field(
+                        "X",
+                        &form.chunks_per_x,
+                        SettingsField::ChunksPerX,
+                        48.0,
+                    ))
+                    .push(field(
+                        "Y",
+                        &form.chunks_per_y,
+                        SettingsField::ChunksPerY,
+                        48.0,
+                    ))
+                    .push(text("Threads"))
+                    .push(field(
+                        "Threads",
+                        &form.thread_count,
+                        SettingsField::ThreadCount,
+                        48.0,
+                    ))
+                    .push(text("FPS"))
+                    .push(field("FPS", &form.fps, SettingsField::Fps, 64.0))
+                    .push(button("Apply").on_press_maybe(
+                        form.settings(&stream.info).map(|_| Message::ApplySettings),
+                    ))
+                    .push(
+                        button("Cancel")
+                            .style(button::secondary)
+                            .on_press(Message::CancelSettings),
+                    )
+            }
+            _ => toolbar.push(
+                button("Stream settings")
+                    .style(button::secondary)
+                    .on_press_maybe(
+                        self.selected_stream()
+                            .is_some_and(|stream| stream.reconfigure.is_some())
+                            .then_some(Message::EditSettings),
+                    ),
+            ),
+        };
+
+        toolbar = match &self.pairing_code {
+            Some(code) => toolbar
+                .push(
+                    text_input("Pairing code", code)
+                        .on_input(Message::PairingCodeChanged)
+                        .on_submit(Message::SubmitPairing)
+                        .width(120.0),
+                )
+                .push(
+                    button("Pair")
+                        .on_press_maybe(self.pairing_code().map(|_| Message::SubmitPairing)),
+                )
+                .push(
+                    button("Cancel")
+                        .style(button::secondary)
+                        .on_press(Message::CancelPairing),
+                ),
+            None if matches!(self.mode, Mode::Replay(_)) => toolbar,
+            None => toolbar.push(
+                button("Pair kindle")
+                    .style(button::secondary)
+                    .on_press_maybe(self.commands.is_some().then_some(Message::StartPairing)),
+            ),
+        };
+
+        toolbar = toolbar.push(
+            checkbox(self.hud)
+                .label("Stats")
+                .on_toggle(Message::ToggleHud),
+        );
+        // Filters and the overlay are up to the shader.
+        if !self.software {
+            toolbar = toolbar
+                .push(pick_list(Filter::ALL, Some(self.filter), Message::Filter))
+                .push(
+                    checkbox(self.sharpen)
+                        .label("Sharpen")
+                        .on_toggle(Message::ToggleSharpen),
+                )
+                .push(
+                    checkbox(self.chunk_overlay)
+                        .label("Chunks")
+                        .on_toggle(Message::ToggleChunkOverlay),
+                );
+        }
+
+        toolbar = toolbar.push(
+            checkbox(self.native_size)
+                .label("1:1")
+                .on_toggle(Message::ToggleNativeSize),
+        );
+
+        if self.devices.len() > 1 {
+            toolbar = toolbar.push(
+                checkbox(self.grid)
+                    .label("Show all")
+                    .on_toggle(Message::ToggleGrid),
+            );
+        }
+
+        let body = if self.grid && self.devices.len() > 1 {
+            let columns = (self.devices.len() as f64).sqrt().ceil() as usize;
+
+            Column::with_children(self.devices.chunks(columns).map(|devices| {
+                row(devices.iter().map(|device| self.device_view(device)))
+                    .spacing(4.0)
+                    .height(Length::Fill)
+                    .into()
+            }))
+            .spacing(4.0)
+            .into()
+        } else {
+            match self.selected.and_then(|id| self.device(id)) {
+                Some(device) => self.device_view(device),
+                None => self.status_view(None),
+            }
+        };
+
+        column![toolbar, body].into()
+    }
+
+    fn device_view<'a>(&'a self, device: &'a Device) -> Element<'a, Message> {
         let mut stack = Stack::new().width(Length::Fill).height(Length::Fill);
 
-        if let Some(stream) = &self.stream {
-            stack = stack.push(
-                shader(KindleView { stream })
+        if let Some(stream) = &device.stream {
+            let view = KindleView {
+                stream,
+                tool: self.tool,
+                inspecting: self.inspecting,
+                pointer_over: self.pointer_over == Some(device.id),
+                selection: device.selection,
+                ruler: device.ruler,
+                chunk_overlay: self.chunk_overlay,
+                filter: self.filter,
+                sharpen: self.sharpen,
+                native_size: self.native_size,
+            };
+
+            // Sized so that a panel pixel lands on a physical pixel.
+            let (width, height) = if self.native_size {
+                let (width, height) = stream.shown_size();
+                let scale_factor = stream.scale_factor();
+
+                (
+                    Length::Fixed(width as f32 / scale_factor),
+                    Length::Fixed(height as f32 / scale_factor),
+                )
+            } else {
+                (Length::Fill, Length::Fill)
+            };
+
+            let view = if self.software {
+                Element::from(canvas(view).width(width).height(height))
+            } else {
+                shader(view).width(width).height(height).into()
+            };
+
+            stack = stack.push(if self.native_size {
+                scrollable(view)
+                    .direction(scrollable::Direction::Both {
+                        vertical: scrollable::Scrollbar::default(),
+                        horizontal: scrollable::Scrollbar::default(),
+                    })
                     .width(Length::Fill)
-                    .height(Length::Fill),
-            );
+                    .height(Length::Fill)
+                    .into()
+            } else {
+                view
+            });
+
+            if self.hud {
+                stack = stack.push(container(device.stats.view()).padding(8.0));
+            }
+
+            if let Some(readout) = inspect::readout(
+                &stream.info,
+                &stream.framebuffer,
+                device.hover,
+                device.ruler,
+            ) {
+                stack = stack.push(container(readout).padding(8.0).align_bottom(Length::Fill));
+            }
         } else {
-            stack = stack.push(center(
-                Column::with_children(self.messages.iter().map(|msg| text!("{}", msg).into()))
-                    .align_x(Alignment::Center)
-                    .spacing(4.0),
-            ));
+            stack = stack.push(self.status_view(Some(device)));
         }
 
         stack.into()
     }
 
+    fn status_view<'a>(&'a self, device: Option<&'a Device>) -> Element<'a, Message> {
+        let messages = self
+            .messages
+            .iter()
+            .map(String::as_str)
+            .chain(device.map(|device| device.status.as_str()));
+
+        center(
+            Column::with_children(messages.map(|msg| text!("{}", msg).into()))
+                .align_x(Alignment::Center)
+                .spacing(4.0),
+        )
+        .into()
+    }
+
     fn subscription(&self) -> Subscription<Message> {
-        Subscription::run(stream)
+        let stream = Subscription::batch([
+            Subscription::run_with(self.mode.clone(), stream),
+            iced::event::listen_with(key_message),
+        ]);
+
+        if self.chunk_overlay {
+            Subscription::batch([
+                stream,
+                iced::time::every(Duration::from_millis(50)).map(|_| Message::Redraw),
+            ])
+        } else {
+            stream
+        }
     }
 
     fn title(&self) -> String {
@@ -96,11 +941,118 @@ impl State {
     }
 }
 
-fn stream() -> impl iced::futures::Stream<Item = Message> {
-    iced::stream::channel(64, async |mut output| {
+impl Device {
+    fn update(&mut self, message: DeviceMessage) {
+        match message {
+            DeviceMessage::Status(status) => self.status = status.to_owned(),
+            DeviceMessage::Incompatible(err) => {
+                self.status = format!("Incompatible kindle: {err}");
+            }
+            DeviceMessage::Connected {
+                info,
+                framebuffer,
+                input,
+                reconfigure,
+            } => {
+                self.stream = Some(Arc::new(StreamState {
+                    device: self.id,
+                    heat: Mutex::new(ChunkHeat::new(info.chunks_per_x, info.chunks_per_y)),
+                    dirty: Mutex::new(DirtyChunks::new(&info)),
+                    software: Mutex::default(),
+                    scale_factor: AtomicU32::new(1.0_f32.to_bits()),
+                    info,
+                    rotation: AtomicU8::new(Rotation::default() as u8),
+                    framebuffer,
+                    input,
+                    reconfigure,
+                }));
+                self.stats.clear();
+            }
+            DeviceMessage::Reconfigured { info, framebuffer } => {
+                let Some(stream) = &self.stream else {
+                    return;
+                };
+
+                // Same connection, only the buffers behind it changed.
+                self.stream = Some(Arc::new(StreamState {
+                    device: self.id,
+                    heat: Mutex::new(ChunkHeat::new(info.chunks_per_x, info.chunks_per_y)),
+                    dirty: Mutex::new(DirtyChunks::new(&info)),
+                    software: Mutex::default(),
+                    scale_factor: AtomicU32::new(1.0_f32.to_bits()),
+                    info,
+                    rotation: AtomicU8::new(stream.rotation.load(Ordering::Relaxed)),
+                    framebuffer,
+                    input: stream.input.clone(),
+                    reconfigure: stream.reconfigure.clone(),
+                }));
+                self.stats.clear();
+            }
+            DeviceMessage::Rotated(rotation) => {
+                let Some(stream) = &self.stream else {
+                    return;
+                };
+
+                stream.rotation.store(rotation as u8, Ordering::Relaxed);
+            }
+            DeviceMessage::Stats(stats) => self.stats.push(stats),
+            DeviceMessage::Updated { chunks } => {
+                let Some(stream) = &self.stream else {
+                    return;
+                };
+
+                stream.heat.lock().unwrap().touch(&chunks);
+                stream.dirty.lock().unwrap().mark(&chunks);
+            }
+            DeviceMessage::Closed => self.stream = None,
+        }
+    }
+}
+
+fn replay_controls(status: &ReplayStatus) -> Element<'_, Message> {
+    let length = status.length.as_secs_f64();
+
+    row![
+        button(if status.paused { "Play" } else { "Pause" })
+            .style(button::secondary)
+            .on_press(Message::Replay(Command::Pause(!status.paused))),
+        slider(0.0..=length, status.position.as_secs_f64(), |position| {
+            Message::Replay(Command::Seek(Duration::from_secs_f64(position)))
+        })
+        .step(0.1)
+        .width(240.0),
+        text!("{:.1}s / {:.1}s", status.position.as_secs_f64(), length),
+        pick_list(REPLAY_SPEEDS, Some(status.speed), |speed| {
+            Message::Replay(Command::Speed(speed))
+        }),
+        text("x"),
+    ]
+    .spacing(4.0)
+    .align_y(Alignment::Center)
+    .into()
+}
+
+fn stream(mode: &Mode) -> impl iced::futures::Stream<Item = Message> + use<> {
+    let mode = mode.clone();
+
+    iced::stream::channel(64, async move |mut output| {
         let (sender, mut receiver) = mpsc::unbounded_channel();
 
-        tokio::spawn(async { kinshare_server::run(sender).await });
+        tokio::spawn(async move {
+            let result = match mode {
+                Mode::Live { record_dir, direct } => {
+                    kinshare_server::run(sender, kinshare_server::Options { record_dir, direct })
+                        .await
+                }
+                Mode::Replay(path) => kinshare_server::replay(&path, sender).await,
+            };
+
+            if let Err(err) = &result {
+                eprintln!("Error: {err:#}");
+            }
+
+            result
+        });
 
         while let Some(message) = receiver.recv().await {
             output.send(Message::Server(message)).await.ok();
@@ -110,27 +1062,421 @@ fn stream() -> impl iced::futures::Stream<Item = Message> {
 
 struct KindleView<'a> {
     stream: &'a Arc<StreamState>,
+    tool: Tool,
+    inspecting: bool,
+    /// Whether keys go to this kindle.
+    pointer_over: bool,
+    selection: Option<Rect>,
+    ruler: Option<Ruler>,
+    chunk_overlay: bool,
+    filter: Filter,
+    sharpen: bool,
+    native_size: bool,
+}
+
+#[derive(Default)]
+struct KindleViewState {
+    /// Last position of the finger while the mouse button is held.
+    touch: Option<(u16, u16)>,
+    /// The tool a selection or measuring drag is done with, and where it
+    /// started.
+    drag: Option<(Tool, (u16, u16))>,
+    /// Last pixel reported as under the cursor.
+    hover: Option<(u16, u16)>,
+    view: ViewTransform,
+    /// Last cursor position while panning.
+    pan_from: Option<Point>,
+}
+
+/// Zoom into the fitted panel. Positions are 0 to 1 across the upright
+/// panel, like `uv` in `shader.wgsl`.
+#[derive(Debug, Clone, Copy)]
+struct ViewTransform {
+    zoom: f32,
+    /// The part of the panel in the middle of the view.
+    center: Point,
+}
+
+impl Default for ViewTransform {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            center: Point::new(0.5, 0.5),
+        }
+    }
 }
 
-impl<Message> shader::Program<Message> for KindleView<'_> {
-    type State = ();
+impl ViewTransform {
+    const MAX_ZOOM: f32 = 64.0;
+
+    /// From a position on the fitted panel to the part of the panel shown
+    /// there.
+    fn to_panel(self, fit: Point) -> Point {
+        Point::new(
+            self.center.x + (fit.x - 0.5) / self.zoom,
+            self.center.y + (fit.y - 0.5) / self.zoom,
+        )
+    }
+
+    /// Zoom by `factor`, keeping what's under `fit` in place.
+    fn zoom_at(&mut self, fit: Point, factor: f32) {
+        let anchor = self.to_panel(fit);
+
+        self.zoom = (self.zoom * factor).clamp(1.0, Self::MAX_ZOOM);
+        self.center = Point::new(
+            anchor.x - (fit.x - 0.5) / self.zoom,
+            anchor.y - (fit.y - 0.5) / self.zoom,
+        );
+        self.clamp();
+    }
+
+    /// Move the panel along with a drag of `delta` on the fitted panel.
+    fn pan(&mut self, delta: Vector) {
+        self.center.x -= delta.x / self.zoom;
+        self.center.y -= delta.y / self.zoom;
+        self.clamp();
+    }
+
+    /// Keep the view on the panel.
+    fn clamp(&mut self) {
+        let margin = 0.5 / self.zoom;
+
+        self.center.x = self.center.x.clamp(margin, 1.0 - margin);
+        self.center.y = self.center.y.clamp(margin, 1.0 - margin);
+    }
+}
+
+impl shader::Program<Message> for KindleView<'_> {
+    type State = KindleViewState;
     type Primitive = KindlePrimitive;
 
+    fn update(
+        &self,
+        state: &mut Self::State,
+        event: &Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> Option<shader::Action<Message>> {
+        self.handle_event(state, event, bounds, cursor)
+    }
+
     fn draw(
         &self,
-        _state: &Self::State,
+        state: &Self::State,
         _cursor: iced::mouse::Cursor,
         _bounds: iced::Rectangle,
     ) -> Self::Primitive {
         KindlePrimitive {
             stream: Arc::clone(self.stream),
+            view: self.view(state),
+            selection: self.selection,
+            ruler: self.ruler,
+            chunk_overlay: self.chunk_overlay,
+            filter: self.filter,
+            sharpen: self.sharpen,
+            native_size: self.native_size,
+        }
+    }
+}
+
+impl KindleView<'_> {
+    /// Input on the view, the same whichever way it's drawn.
+    fn handle_event(
+        &self,
+        state: &mut KindleViewState,
+        event: &Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> Option<shader::Action<Message>> {
+        if let Some(action) = self.update_pointer(state, event, bounds, cursor) {
+            return Some(action);
+        }
+
+        if let Some(action) = self.update_view(state, event, bounds, cursor) {
+            return Some(action);
+        }
+
+        // Moves are only reported while nothing's being dragged, a drag has
+        // more important things to say.
+        if state.drag.is_none()
+            && state.touch.is_none()
+            && let Some(action) = self.update_hover(state, event, bounds)
+        {
+            return Some(action);
+        }
+
+        if self.tool != Tool::Touch || state.drag.is_some() {
+            return self.update_drag(state, event, bounds, cursor);
+        }
+
+        let view = self.view(state);
+
+        self.stream.input.as_ref()?;
+
+        let touch = |phase, (x, y)| {
+            Some(
+                shader::Action::publish(Message::Input(
+                    self.stream.device,
+                    InputEvent::Touch { phase, x, y },
+                ))
+                .and_capture(),
+            )
+        };
+
+        match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let position = self.stream.panel_position(
+                    bounds,
+                    view,
+                    cursor.position_over(bounds)?,
+                    false,
+                )?;
+
+                state.touch = Some(position);
+                touch(TouchPhase::Down, position)
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                state.touch?;
+
+                let position = self.stream.panel_position(bounds, view, *position, true)?;
+
+                if state.touch == Some(position) {
+                    return None;
+                }
+
+                state.touch = Some(position);
+                touch(TouchPhase::Move, position)
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                touch(TouchPhase::Up, state.touch.take()?)
+            }
+            _ => None,
+        }
+    }
+
+    /// The zoom and pan to draw with, none in 1:1 mode where the view
+    /// scrolls instead.
+    fn view(&self, state: &KindleViewState) -> ViewTransform {
+        if self.native_size {
+            ViewTransform::default()
+        } else {
+            state.view
+        }
+    }
+
+    /// Zoom with the wheel around the cursor, pan by dragging with the right
+    /// or middle button, and go back to the whole panel with Ctrl+0.
+    fn update_view(
+        &self,
+        state: &mut KindleViewState,
+        event: &Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> Option<shader::Action<Message>> {
+        if self.native_size {
+            return None;
+        }
+
+        let redraw = || Some(shader::Action::request_redraw().and_capture());
+
+        match event {
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
+                let position = cursor.position_over(bounds)?;
+                let steps = match *delta {
+                    mouse::ScrollDelta::Lines { y, .. } => y,
+                    mouse::ScrollDelta::Pixels { y, .. } => y / 40.0,
+                };
+
+                state.view.zoom_at(
+                    self.stream.fit_position(bounds, position),
+                    1.25_f32.powf(steps),
+                );
+                redraw()
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(
+                mouse::Button::Right | mouse::Button::Middle,
+            )) => {
+                state.pan_from = Some(cursor.position_over(bounds)?);
+                Some(shader::Action::capture())
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                let from = state.pan_from?;
+                state.pan_from = Some(*position);
+
+                let fit = self.stream.fit_position(bounds, *position);
+                let fit_from = self.stream.fit_position(bounds, from);
+
+                state.view.pan(fit - fit_from);
+                redraw()
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(
+                mouse::Button::Right | mouse::Button::Middle,
+            )) => {
+                state.pan_from.take()?;
+                Some(shader::Action::capture())
+            }
+            Event::Keyboard(keyboard::Event::KeyPressed {
+                physical_key: key::Physical::Code(key::Code::Digit0),
+                modifiers,
+                ..
+            }) if modifiers.command() && cursor.is_over(bounds) => {
+                state.view = ViewTransform::default();
+                redraw()
+            }
+            _ => None,
+        }
+    }
+
+    /// Drags with the selection and measuring tools. The drag finishes with
+    /// the tool it started with, even if another was picked meanwhile.
+    fn update_drag(
+        &self,
+        state: &mut KindleViewState,
+        event: &Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> Option<shader::Action<Message>> {
+        let view = self.view(state);
+        let drag = |tool, start: (u16, u16), end: (u16, u16)| {
+            let device = self.stream.device;
+            let message = if tool == Tool::Measure {
+                Message::Ruler(
+                    device,
+                    Some(Ruler {
+                        from: start,
+                        to: end,
+                    }),
+                )
+            } else {
+                let (left, right) = (start.0.min(end.0) as usize, start.0.max(end.0) as usize);
+                let (top, bottom) = (start.1.min(end.1) as usize, start.1.max(end.1) as usize);
+
+                Message::Selection(
+                    device,
+                    Some(Rect {
+                        x: left,
+                        y: top,
+                        width: right - left + 1,
+                        height: bottom - top + 1,
+                    }),
+                )
+            };
+
+            Some(shader::Action::publish(message).and_capture())
+        };
+
+        match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let position = self.stream.panel_position(
+                    bounds,
+                    view,
+                    cursor.position_over(bounds)?,
+                    false,
+                )?;
+
+                state.drag = Some((self.tool, position));
+                drag(self.tool, position, position)
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                let (tool, start) = state.drag?;
+
+                drag(
+                    tool,
+                    start,
+                    self.stream.panel_position(bounds, view, *position, true)?,
+                )
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => {
+                let (tool, _) = state.drag.take()?;
+
+                Some(match tool {
+                    Tool::Select => {
+                        shader::Action::publish(Message::CopySelection(self.stream.device))
+                            .and_capture()
+                    }
+                    _ => shader::Action::capture(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Report the cursor going over or leaving the view, so keys go to the
+    /// kindle under it. Left alone mid-drag, the drag needs every move.
+    fn update_pointer(
+        &self,
+        state: &mut KindleViewState,
+        event: &Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> Option<shader::Action<Message>> {
+        let over = match event {
+            Event::Mouse(mouse::Event::CursorMoved { .. } | mouse::Event::CursorEntered) => {
+                cursor.is_over(bounds)
+            }
+            Event::Mouse(mouse::Event::CursorLeft) => false,
+            _ => return None,
+        };
+
+        if over == self.pointer_over
+            || state.touch.is_some()
+            || state.drag.is_some()
+            || state.pan_from.is_some()
+        {
+            return None;
+        }
+
+        Some(shader::Action::publish(Message::PointerOver(
+            self.stream.device,
+            over,
+        )))
+    }
+
+    /// Report the pixel under the cursor while inspecting, whenever it's a
+    /// different one.
+    fn update_hover(
+        &self,
+        state: &mut KindleViewState,
+        event: &Event,
+        bounds: Rectangle,
+    ) -> Option<shader::Action<Message>> {
+        if !self.inspecting {
+            return None;
+        }
+
+        let hover = match event {
+            Event::Mouse(mouse::Event::CursorMoved { position }) => {
+                self.stream
+                    .panel_position(bounds, self.view(state), *position, false)
+            }
+            Event::Mouse(mouse::Event::CursorLeft) => None,
+            _ => return None,
+        };
+
+        if hover == state.hover {
+            return None;
         }
+
+        state.hover = hover;
+
+        // Not captured, so widgets underneath still see the move.
+        Some(shader::Action::publish(Message::Hover(
+            self.stream.device,
+            hover,
+        )))
     }
 }
 
 #[derive(Debug)]
 struct KindlePrimitive {
     stream: Arc<StreamState>,
+    view: ViewTransform,
+    selection: Option<Rect>,
+    ruler: Option<Ruler>,
+    chunk_overlay: bool,
+    filter: Filter,
+    sharpen: bool,
+    native_size: bool,
 }
 
 impl shader::Primitive for KindlePrimitive {
@@ -141,55 +1487,122 @@ impl shader::Primitive for KindlePrimitive {
         pipeline: &mut Self::Pipeline,
         device: &iced::wgpu::Device,
         queue: &iced::wgpu::Queue,
-        _bounds: &iced::Rectangle,
+        bounds: &iced::Rectangle,
         viewport: &iced::widget::shader::Viewport,
     ) {
+        let id = self.stream.device;
+
+        self.stream
+            .scale_factor
+            .store(viewport.scale_factor().to_bits(), Ordering::Relaxed);
+
+        let (display_width, display_height) = (
+            self.stream.info.display_width as u32,
+            self.stream.info.display_height as u32,
+        );
+
         let standard_uniform = StandardUniform {
             screen_size: [
-                viewport.physical_width() as f32,
-                viewport.physical_height() as f32,
+                bounds.width * viewport.scale_factor(),
+                bounds.height * viewport.scale_factor(),
             ],
             kindle_size: [
                 self.stream.info.display_width as f32,
                 self.stream.info.display_height as f32,
             ],
+            rotation: self.stream.rotation() as u32,
+            chunk_overlay: self.chunk_overlay as u32,
+            chunks: [
+                self.stream.info.chunks_per_x as u32,
+                self.stream.info.chunks_per_y as u32,
+            ],
+            filter_mode: self.filter as u32,
+            native_size: self.native_size as u32,
+            sharpen: if self.sharpen { SHARPEN } else { 0.0 },
+            zoom: self.view.zoom,
+            center: [self.view.center.x, self.view.center.y],
+            _padding: [0; 2],
+            selection: self.selection.map_or([0.0; 4], |selection| {
+                [
+                    selection.x as f32 / display_width as f32,
+                    selection.y as f32 / display_height as f32,
+                    selection.right() as f32 / display_width as f32,
+                    selection.bottom() as f32 / display_height as f32,
+                ]
+            }),
+            ruler: self.ruler.map_or([-1.0; 4], |ruler| {
+                [
+                    ruler.from.0 as f32,
+                    ruler.from.1 as f32,
+                    ruler.to.0 as f32,
+                    ruler.to.1 as f32,
+                ]
+            }),
         };
 
-        if pipeline.inner.is_none() {
-            pipeline.init(
-                device,
-                self.stream.info.display_width as u32,
-                self.stream.info.display_height as u32,
-                standard_uniform,
-            );
+        // Kindles that disconnected or were dropped keep nothing on the GPU.
+        pipeline
+            .inner
+            .retain(|_, inner| inner.stream.strong_count() > 0);
+
+        // Every connection and reconfiguration comes with a new stream, which
+        // may have a different resolution or grid.
+        let init = pipeline
+            .inner
+            .get(&id)
+            .is_none_or(|inner| inner.stream.as_ptr() != Arc::as_ptr(&self.stream));
+
+        if init {
+            pipeline.init(device, &self.stream, standard_uniform);
         }
 
-        pipeline.update_standard(queue, standard_uniform);
+        let rects = {
+            let mut dirty = self.stream.dirty.lock().unwrap();
 
-        if self.stream.updated.swap(false, Ordering::AcqRel) {
-            pipeline.update_screen(
-                queue,
-                self.stream.info.display_width as u32,
-                self.stream.info.display_height as u32,
-                &self.stream.framebuffer.lock().unwrap(),
-            );
+            // A fresh texture starts out blank.
+            if init {
+                dirty.mark_all();
+            }
+
+            dirty.take()
+        };
+
+        pipeline.update_standard(queue, id, standard_uniform);
+
+        if self.chunk_overlay {
+            pipeline.update_heat(queue, id, &self.stream.heat.lock().unwrap().intensities());
+        }
+
+        if !rects.is_empty() {
+            pipeline.update_screen(queue, id, &self.stream.framebuffer, &rects);
         }
     }
 
     fn draw(&self, pipeline: &Self::Pipeline, render_pass: &mut wgpu::RenderPass<'_>) -> bool {
-        pipeline.draw(render_pass);
+        pipeline.draw(self.stream.device, render_pass);
 
         false
     }
 }
 
+/// Shared by every [`KindleView`], so each device gets its own texture and
+/// uniforms.
 struct KindlePipeline {
     format: iced::wgpu::TextureFormat,
-    inner: Option<KindlePipelineInner>,
+    inner: HashMap<DeviceId, KindlePipelineInner>,
 }
 
 struct KindlePipelineInner {
+    /// What this was made for, gone once the kindle's stream is.
+    stream: Weak<StreamState>,
+    display_width: u32,
+    chunks: [u32; 2],
     screen_texture: wgpu::Texture,
+    /// Dirty parts of the framebuffer, copied out so its lock can be let go
+    /// before uploading.
+    staging: Vec<u8>,
+    /// One texel per chunk, how recently it changed.
+    heat_texture: wgpu::Texture,
     screen_texture_bind_group: wgpu::BindGroup,
     standard_uniform_buffer: wgpu::Buffer,
     standard_uniform_bind_group: wgpu::BindGroup,
@@ -201,6 +1614,58 @@ struct KindlePipelineInner {
 struct StandardUniform {
     screen_size: [f32; 2],
     kindle_size: [f32; 2],
+    rotation: u32,
+    /// Whether to draw the chunk grid and heat.
+    chunk_overlay: u32,
+    /// Chunks per row and column.
+    chunks: [u32; 2],
+    /// Left, top, right and bottom of the selection in texture coordinates,
+    /// all zero when there is none.
+    selection: [f32; 4],
+    filter_mode: u32,
+    /// Whether one panel pixel goes to one screen pixel, from the top left.
+    native_size: u32,
+    /// How much of the difference to its surroundings gets added to a pixel,
+    /// zero for none.
+    sharpen: f32,
+    zoom: f32,
+    /// The part of the upright panel in the middle of the view.
+    center: [f32; 2],
+    _padding: [u32; 2],
+    /// Ends of the ruler in framebuffer pixels, all negative when there's
+    /// none.
+    ruler: [f32; 4],
+}
+
+/// Strength of the sharpening when it's on, plenty for text without halos
+/// around everything.
+const SHARPEN: f32 = 0.6;
+
+/// How the panel is scaled to the window, in the order `shader.wgsl`
+/// numbers them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Filter {
+    Nearest,
+    Bilinear,
+    /// Catmull-Rom, sharper than bilinear.
+    Bicubic,
+    /// Lanczos with three lobes, sharpest but slowest.
+    Lanczos,
+}
+
+impl Filter {
+    const ALL: [Self; 4] = [Self::Nearest, Self::Bilinear, Self::Bicubic, Self::Lanczos];
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Nearest => "Nearest",
+            Self::Bilinear => "Bilinear",
+            Self::Bicubic => "Bicubic",
+            Self::Lanczos => "Lanczos",
+        })
+    }
 }
 
 impl shader::Pipeline for KindlePipeline {
@@ -214,7 +1679,7 @@ impl shader::Pipeline for KindlePipeline {
     {
         Self {
             format,
-            inner: None,
+            inner: HashMap::new(),
         }
     }
 }
@@ -223,10 +1688,14 @@ impl KindlePipeline {
     fn init(
         &mut self,
         device: &iced::wgpu::Device,
-        display_width: u32,
-        display_height: u32,
+        stream: &Arc<StreamState>,
         standard_uniform: StandardUniform,
     ) {
+        let (display_width, display_height) = (
+            stream.info.display_width as u32,
+            stream.info.display_height as u32,
+        );
+
         let screen_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Screen Texture"),
             size: wgpu::Extent3d {
@@ -245,6 +1714,25 @@ impl KindlePipeline {
         let screen_texture_view =
             screen_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
+        let heat_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Heat Texture"),
+            size: wgpu::Extent3d {
+                width: standard_uniform.chunks[0],
+                height: standard_uniform.chunks[1],
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let heat_texture_view = heat_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Only `Filter::Bilinear` samples through this, the other filters
+        // load texels in the shader.
         let screen_texture_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
@@ -271,6 +1759,16 @@ impl KindlePipeline {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -286,6 +1784,10 @@ impl KindlePipeline {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&screen_texture_sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&heat_texture_view),
+                },
             ],
         });
 
@@ -365,17 +1867,30 @@ impl KindlePipeline {
             cache: None,
         });
 
-        self.inner = Some(KindlePipelineInner {
-            screen_texture,
-            screen_texture_bind_group,
-            standard_uniform_buffer,
-            standard_uniform_bind_group,
-            render_pipeline,
-        });
+        self.inner.insert(
+            stream.device,
+            KindlePipelineInner {
+                stream: Arc::downgrade(stream),
+                display_width,
+                chunks: standard_uniform.chunks,
+                screen_texture,
+                staging: Vec::new(),
+                heat_texture,
+                screen_texture_bind_group,
+                standard_uniform_buffer,
+                standard_uniform_bind_group,
+                render_pipeline,
+            },
+        );
     }
 
-    fn update_standard(&self, queue: &wgpu::Queue, standard_uniform: StandardUniform) {
-        let Some(inner) = &self.inner else {
+    fn update_standard(
+        &self,
+        queue: &wgpu::Queue,
+        id: DeviceId,
+        standard_uniform: StandardUniform,
+    ) {
+        let Some(inner) = self.inner.get(&id) else {
             return;
         };
 
@@ -386,40 +1901,99 @@ impl KindlePipeline {
         );
     }
 
+    /// Upload the `rects` of `framebuffer`. The lock is only held while
+    /// they're copied out, the decoder is waiting on it.
     fn update_screen(
-        &self,
+        &mut self,
         queue: &wgpu::Queue,
-        display_width: u32,
-        display_height: u32,
-        screen: &[u8],
+        id: DeviceId,
+        framebuffer: &Mutex<Box<[u8]>>,
+        rects: &[Rect],
     ) {
-        let Some(inner) = &self.inner else {
+        let Some(inner) = self.inner.get_mut(&id) else {
             return;
         };
 
+        let width = inner.display_width as usize;
+
+        inner.staging.clear();
+
+        {
+            let framebuffer = framebuffer.lock().unwrap();
+
+            for rect in rects {
+                for y in rect.y..rect.bottom() {
+                    inner.staging.extend_from_slice(
+                        &framebuffer[y * width + rect.x..y * width + rect.right()],
+                    );
+                }
+            }
+        }
+
+        let mut offset = 0;
+
+        for rect in rects {
+            let len = rect.width * rect.height;
+
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &inner.screen_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: rect.x as u32,
+                        y: rect.y as u32,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &inner.staging[offset..offset + len],
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(rect.width as u32),
+                    rows_per_image: Some(rect.height as u32),
+                },
+                wgpu::Extent3d {
+                    width: rect.width as u32,
+                    height: rect.height as u32,
+                    depth_or_array_layers: 1,
+                },
+            );
+
+            offset += len;
+        }
+    }
+
+    /// Upload [`ChunkHeat::intensities`] for the overlay.
+    fn update_heat(&self, queue: &wgpu::Queue, id: DeviceId, intensities: &[u8]) {
+        let Some(inner) = self.inner.get(&id) else {
+            return;
+        };
+
+        let [width, height] = inner.chunks;
+
         queue.write_texture(
             wgpu::TexelCopyTextureInfo {
-                texture: &inner.screen_texture,
+                texture: &inner.heat_texture,
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            screen,
+            intensities,
             wgpu::TexelCopyBufferLayout {
                 offset: 0,
-                bytes_per_row: Some(display_width),
-                rows_per_image: Some(display_height),
+                bytes_per_row: Some(width),
+                rows_per_image: Some(height),
             },
             wgpu::Extent3d {
-                width: display_width,
-                height: display_height,
+                width,
+                height,
                 depth_or_array_layers: 1,
             },
         );
     }
 
-    fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>) {
-        let Some(inner) = &self.inner else {
+    fn draw(&self, id: DeviceId, render_pass: &mut wgpu::RenderPass<'_>) {
+        let Some(inner) = self.inner.get(&id) else {
             return;
         };
 
@@ -18,34 +18,231 @@ fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
 
 @group(0) @binding(0)
 var screen_texture: texture_2d<f32>;
+// Only used by the bilinear filter, the others load texels themselves
 @group(0) @binding(1)
 var screen_sampler: sampler;
+// One texel per chunk, brighter the more recently it changed
+@group(0) @binding(2)
+var heat_texture: texture_2d<f32>;
 
 struct StandardUniform {
     screen_size: vec2<f32>,
     kindle_size: vec2<f32>,
+    // Quarter turns clockwise needed to show the panel upright
+    rotation: u32,
+    // Nonzero to draw the chunk grid and heat
+    chunk_overlay: u32,
+    // Chunks per row and column
+    chunks: vec2<u32>,
+    // Left, top, right and bottom in texture coordinates, empty when unset
+    selection: vec4<f32>,
+    // 0 nearest, 1 bilinear, 2 bicubic, 3 Lanczos
+    filter_mode: u32,
+    // Nonzero to map panel pixels to screen pixels one to one instead of
+    // fitting the panel in
+    native_size: u32,
+    // Strength of the unsharp mask, 0 for none
+    sharpen: f32,
+    // How far the view is zoomed in, 1 for the whole panel
+    zoom: f32,
+    // The part of the upright panel in the middle of the view
+    center: vec2<f32>,
+    // Ends of the ruler in panel pixels as sent, negative when unset
+    ruler: vec4<f32>,
 };
 
 @group(1) @binding(0)
 var<uniform> standard: StandardUniform;
 
+// Maps a position on the upright image back to the texture as sent
+fn unrotate(uv: vec2<f32>) -> vec2<f32> {
+    switch standard.rotation {
+        case 1u: {
+            return vec2(uv.y, 1.0 - uv.x);
+        }
+        case 2u: {
+            return 1.0 - uv;
+        }
+        case 3u: {
+            return vec2(1.0 - uv.y, uv.x);
+        }
+        default: {
+            return uv;
+        }
+    }
+}
+
+// Tints the chunk under `texel` by its heat and draws a line a screen pixel
+// wide along the chunk edges
+fn chunk_overlay(color: vec3<f32>, texel: vec2<f32>, grid: vec2<f32>, grid_width: vec2<f32>) -> vec3<f32> {
+    // Same split as Info::chunk_rect, pixel p of a side w cut into c chunks
+    // lands in chunk ((p + 1) * c - 1) / w
+    let size = vec2<u32>(standard.kindle_size);
+    let pixel = min(vec2<u32>(texel * standard.kindle_size), size - 1u);
+    let chunk = ((pixel + 1u) * standard.chunks - 1u) / size;
+
+    let heat = textureLoad(heat_texture, chunk, 0).x;
+    var tinted = mix(color, vec3(1.0, 0.2, 0.1), heat * 0.6);
+
+    let edge = abs(grid - round(grid)) / max(grid_width, vec2(1e-6));
+    if min(edge.x, edge.y) < 0.5 {
+        tinted = mix(tinted, vec3(0.1, 0.4, 1.0), 0.5);
+    }
+
+    return tinted;
+}
+
+const PI: f32 = 3.14159265;
+
+// Catmull-Rom for a radius of 2, three lobe Lanczos for 3
+fn kernel(x: f32, radius: i32) -> f32 {
+    let a = abs(x);
+
+    if radius == 2 {
+        if a < 1.0 {
+            return (1.5 * a - 2.5) * a * a + 1.0;
+        }
+        if a < 2.0 {
+            return ((-0.5 * a + 2.5) * a - 4.0) * a + 2.0;
+        }
+        return 0.0;
+    }
+
+    if a < 1e-5 {
+        return 1.0;
+    }
+    if a >= 3.0 {
+        return 0.0;
+    }
+
+    let x_pi = PI * a;
+    return 3.0 * sin(x_pi) * sin(x_pi / 3.0) / (x_pi * x_pi);
+}
+
+// Weighs the texels within `radius` of `texel` by `kernel`
+fn convolve(texel: vec2<f32>, radius: i32) -> f32 {
+    let size = vec2<i32>(textureDimensions(screen_texture));
+    let position = texel * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(position));
+    let fraction = position - floor(position);
+
+    var sum = 0.0;
+    var weights = 0.0;
+
+    for (var y = 1 - radius; y <= radius; y++) {
+        let weight_y = kernel(f32(y) - fraction.y, radius);
+
+        for (var x = 1 - radius; x <= radius; x++) {
+            let weight = kernel(f32(x) - fraction.x, radius) * weight_y;
+            let at = clamp(base + vec2(x, y), vec2(0), size - 1);
+
+            sum += weight * textureLoad(screen_texture, at, 0).x;
+            weights += weight;
+        }
+    }
+
+    return clamp(sum / weights, 0.0, 1.0);
+}
+
+fn sample(texel: vec2<f32>) -> f32 {
+    switch standard.filter_mode {
+        case 0u: {
+            let size = vec2<i32>(textureDimensions(screen_texture));
+            let at = clamp(vec2<i32>(floor(texel * vec2<f32>(size))), vec2(0), size - 1);
+            return textureLoad(screen_texture, at, 0).x;
+        }
+        case 2u: {
+            return convolve(texel, 2);
+        }
+        case 3u: {
+            return convolve(texel, 3);
+        }
+        default: {
+            return textureSampleLevel(screen_texture, screen_sampler, texel, 0.0).x;
+        }
+    }
+}
+
+// Unsharp mask, pushes a pixel away from the average of its neighbours a
+// panel pixel away, sampled with the same filter so nearest stays sharp
+fn sharpen(gray: f32, texel: vec2<f32>) -> f32 {
+    let step = 1.0 / standard.kindle_size;
+    let blur = (
+        sample(texel + vec2(step.x, 0.0)) +
+        sample(texel - vec2(step.x, 0.0)) +
+        sample(texel + vec2(0.0, step.y)) +
+        sample(texel - vec2(0.0, step.y))
+    ) / 4.0;
+
+    return clamp(gray + standard.sharpen * (gray - blur), 0.0, 1.0);
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let screen_aspect_ratio = standard.kindle_size.x / standard.kindle_size.y;
+    let kindle_size = select(standard.kindle_size, standard.kindle_size.yx, standard.rotation % 2u == 1u);
+    let screen_aspect_ratio = kindle_size.x / kindle_size.y;
     let display_aspect_ratio = standard.screen_size.x / standard.screen_size.y;
 
-    var uv = vec2(in.clip_position.x / standard.screen_size.x, in.clip_position.y / standard.screen_size.y) - 0.5;
+    // The viewport covers just this view, so its clip space maps to its bounds
+    var uv = vec2(in.vertex_position.x, -in.vertex_position.y) * 0.5;
 
-    if screen_aspect_ratio < display_aspect_ratio {
-        uv.x /= screen_aspect_ratio / display_aspect_ratio;
+    if standard.native_size != 0u {
+        uv = (uv + 0.5) * standard.screen_size / kindle_size;
     } else {
-        uv.y /= display_aspect_ratio / screen_aspect_ratio;
+        if screen_aspect_ratio < display_aspect_ratio {
+            uv.x /= screen_aspect_ratio / display_aspect_ratio;
+        } else {
+            uv.y /= display_aspect_ratio / screen_aspect_ratio;
+        }
+
+        uv += 0.5;
     }
 
-    uv += 0.5;
+    // Zoomed in, the panel may cover the border too
+    uv = standard.center + (uv - 0.5) / standard.zoom;
+
+    let texel = unrotate(uv);
+
+    // Derivatives have to be taken outside the branch below
+    let grid = texel * vec2<f32>(standard.chunks);
+    let grid_width = fwidth(grid);
+    let pixels = texel * standard.kindle_size;
+    let pixel_width = fwidth(pixels);
 
     if uv.x >= 0.0 && uv.x <= 1.0 && uv.y >= 0.0 && uv.y <= 1.0 {
-        return vec4<f32>(textureSample(screen_texture, screen_sampler, uv).xxx, 1.0);
+        var gray = sample(texel);
+
+        if standard.sharpen > 0.0 {
+            gray = sharpen(gray, texel);
+        }
+
+        var color = vec3(gray);
+
+        if standard.chunk_overlay != 0u {
+            color = chunk_overlay(color, texel, grid, grid_width);
+        }
+
+        // Dim everything outside the selection
+        let selection = standard.selection;
+        if selection.z > selection.x && (any(texel < selection.xy) || any(texel > selection.zw)) {
+            color *= 0.6;
+        }
+
+        // The ruler, a line about a screen pixel wide between the centers of
+        // its end pixels
+        let ruler = standard.ruler;
+        if ruler.x >= 0.0 {
+            let start = ruler.xy + 0.5;
+            let along = ruler.zw + 0.5 - start;
+            let t = clamp(dot(pixels - start, along) / max(dot(along, along), 1e-6), 0.0, 1.0);
+            let dist = distance(pixels, start + along * t);
+
+            if dist < max(pixel_width.x, pixel_width.y) {
+                color = mix(color, vec3(1.0, 0.6, 0.0), 0.9);
+            }
+        }
+
+        return vec4<f32>(color, 1.0);
     }
 
     let dist = distance(uv, vec2(0.5));
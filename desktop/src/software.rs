@@ -0,0 +1,297 @@
+//! Drawing streams without a GPU. The shader path needs a wgpu adapter,
+//! which headless machines, VMs and some remote desktops don't have, so
+//! there iced falls back to tiny-skia and views are drawn as canvas images
+//! instead, letterboxed the same way but without the chunk overlay.
+
+use iced::widget::canvas;
+use iced::widget::image::{FilterMethod, Handle};
+use iced::{Color, Event, Point, Rectangle, Renderer, Size, Theme, mouse, wgpu};
+
+use kinshare_shared::messages::{Rect, Rotation};
+
+use crate::{KindleView, KindleViewState, Message, StreamState, ViewTransform};
+
+/// Whether the GPU path can't be used, either because iced was told to use
+/// tiny-skia through `ICED_BACKEND` or because there's no adapter for it.
+pub fn required() -> bool {
+    if let Ok(backends) = std::env::var("ICED_BACKEND") {
+        return backends.split(',').next() == Some("tiny-skia");
+    }
+
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::from_env().unwrap_or(wgpu::Backends::all()),
+        ..Default::default()
+    });
+
+    iced::futures::executor::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference:
+            wgpu::PowerPreference::from_env().unwrap_or(wgpu::PowerPreference::HighPerformance),
+        compatible_surface: None,
+        force_fallback_adapter: false,
+    }))
+    .is_err()
+}
+
+/// Side of the square tiles a stream is drawn in, in framebuffer pixels.
+/// Only the tiles a frame touched are made again, a whole image would have
+/// to be copied and rasterized again for every change.
+const TILE_SIZE: usize = 256;
+
+/// The last images made of a stream, and the rotation they were made for.
+#[derive(Debug, Default)]
+pub struct SoftwareImage {
+    rotation: Option<Rotation>,
+    tiles: Vec<(Rect, Handle)>,
+}
+
+/// Upright RGBA copies of the framebuffer in tiles, each only remade when
+/// part of it changed.
+fn tiles(stream: &StreamState) -> Vec<(Rect, Handle)> {
+    let rotation = stream.rotation();
+    let mut software = stream.software.lock().unwrap();
+    let (width, height) = (stream.info.display_width, stream.info.display_height);
+
+    let rects = {
+        let mut dirty = stream.dirty.lock().unwrap();
+
+        if software.rotation == Some(rotation) && dirty.is_empty() {
+            return software.tiles.clone();
+        }
+
+        // Every pixel moves with the rotation.
+        if software.rotation != Some(rotation) {
+            dirty.mark_all();
+        }
+
+        dirty.take()
+    };
+
+    // A new stream gets a new `SoftwareImage`, with everything dirty.
+    if software.tiles.is_empty() {
+        software.tiles = (0..height)
+            .step_by(TILE_SIZE)
+            .flat_map(|y| {
+                (0..width).step_by(TILE_SIZE).map(move |x| Rect {
+                    x,
+                    y,
+                    width: TILE_SIZE.min(width - x),
+                    height: TILE_SIZE.min(height - y),
+                })
+            })
+            .map(|rect| (rect, Handle::from_rgba(0, 0, Vec::new())))
+            .collect();
+    }
+
+    software.rotation = Some(rotation);
+
+    let framebuffer = stream.framebuffer.lock().unwrap();
+
+    for (tile, handle) in &mut software.tiles {
+        let touched = rects.iter().any(|rect| {
+            rect.x < tile.right()
+                && tile.x < rect.right()
+                && rect.y < tile.bottom()
+                && tile.y < rect.bottom()
+        });
+
+        if touched {
+            *handle = tile_image(&framebuffer, width, *tile, rotation);
+        }
+    }
+
+    software.tiles.clone()
+}
+
+/// The pixels of `tile`, turned upright.
+fn tile_image(framebuffer: &[u8], stride: usize, tile: Rect, rotation: Rotation) -> Handle {
+    let (width, height) = (tile.width, tile.height);
+
+    let (shown_width, shown_height) = if rotation.is_sideways() {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    let mut rgba = vec![0xff; width * height * 4];
+
+    for v in 0..height {
+        let row = &framebuffer[(tile.y + v) * stride + tile.x..][..width];
+
+        for (u, &gray) in row.iter().enumerate() {
+            // The inverse of `unrotate` in `shader.wgsl`, in whole pixels.
+            let (x, y) = match rotation {
+                Rotation::Upright => (u, v),
+                Rotation::Clockwise => (height - 1 - v, u),
+                Rotation::UpsideDown => (width - 1 - u, height - 1 - v),
+                Rotation::CounterClockwise => (v, width - 1 - u),
+            };
+            let at = (y * shown_width + x) * 4;
+
+            rgba[at..at + 3].fill(gray);
+        }
+    }
+
+    Handle::from_rgba(shown_width as u32, shown_height as u32, rgba)
+}
+
+/// Where the panel goes in `bounds`, fitted and centered like the shader,
+/// then zoomed in by `view`.
+fn fit(stream: &StreamState, view: ViewTransform, bounds: Size) -> Rectangle {
+    let (width, height) = (
+        stream.info.display_width as f32,
+        stream.info.display_height as f32,
+    );
+    let shown = if stream.rotation().is_sideways() {
+        Size::new(height, width)
+    } else {
+        Size::new(width, height)
+    };
+
+    let scale = (bounds.width / shown.width).min(bounds.height / shown.height);
+    let size = shown * scale;
+    let fitted = Rectangle::new(
+        Point::new(
+            (bounds.width - size.width) / 2.0,
+            (bounds.height - size.height) / 2.0,
+        ),
+        size,
+    );
+
+    // The inverse of `ViewTransform::to_panel` at the panel's corners.
+    Rectangle::new(
+        Point::new(
+            fitted.x + (0.5 - view.center.x * view.zoom) * fitted.width,
+            fitted.y + (0.5 - view.center.y * view.zoom) * fitted.height,
+        ),
+        fitted.size() * view.zoom,
+    )
+}
+
+/// A point on the framebuffer as sent, in pixels, on the upright panel from
+/// 0 to 1 on both axes.
+fn upright_point(stream: &StreamState, x: f32, y: f32) -> Point {
+    let (u, v) = (
+        x / stream.info.display_width as f32,
+        y / stream.info.display_height as f32,
+    );
+
+    match stream.rotation() {
+        Rotation::Upright => Point::new(u, v),
+        Rotation::Clockwise => Point::new(1.0 - v, u),
+        Rotation::UpsideDown => Point::new(1.0 - u, 1.0 - v),
+        Rotation::CounterClockwise => Point::new(v, 1.0 - u),
+    }
+}
+
+/// `selection` on the upright panel, from 0 to 1 on both axes.
+fn upright(stream: &StreamState, selection: Rect) -> Rectangle {
+    let (a, b) = (
+        upright_point(stream, selection.x as f32, selection.y as f32),
+        upright_point(stream, selection.right() as f32, selection.bottom() as f32),
+    );
+
+    Rectangle::new(
+        Point::new(a.x.min(b.x), a.y.min(b.y)),
+        Size::new((a.x - b.x).abs(), (a.y - b.y).abs()),
+    )
+}
+
+impl canvas::Program<Message> for KindleView<'_> {
+    type State = KindleViewState;
+
+    fn update(
+        &self,
+        state: &mut Self::State,
+        event: &Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> Option<canvas::Action<Message>> {
+        self.handle_event(state, event, bounds, cursor)
+    }
+
+    fn draw(
+        &self,
+        state: &Self::State,
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let mut frame = canvas::Frame::new(renderer, bounds.size());
+        let panel = fit(self.stream, self.view(state), bounds.size());
+
+        frame.fill_rectangle(Point::ORIGIN, bounds.size(), Color::from_rgb(0.6, 0.6, 0.6));
+
+        // Zoomed in, the panel reaches past the view.
+        frame.with_clip(Rectangle::with_size(bounds.size()), |frame| {
+            for (tile, handle) in tiles(self.stream) {
+                let tile = upright(self.stream, tile);
+                // Rounded, so neighbouring tiles meet without a seam.
+                let (left, top, right, bottom) = (
+                    (panel.x + tile.x * panel.width).round(),
+                    (panel.y + tile.y * panel.height).round(),
+                    (panel.x + (tile.x + tile.width) * panel.width).round(),
+                    (panel.y + (tile.y + tile.height) * panel.height).round(),
+                );
+
+                frame.draw_image(
+                    Rectangle::new(Point::new(left, top), Size::new(right - left, bottom - top)),
+                    canvas::Image::new(handle).filter_method(FilterMethod::Linear),
+                );
+            }
+
+            if let Some(selection) = self.selection {
+                dim_around(frame, panel, upright(self.stream, selection));
+            }
+
+            if let Some(ruler) = self.ruler {
+                // Between the centers of the end pixels, like the shader.
+                let [from, to] = [ruler.from, ruler.to].map(|(x, y)| {
+                    let point = upright_point(self.stream, x as f32 + 0.5, y as f32 + 0.5);
+
+                    Point::new(
+                        panel.x + point.x * panel.width,
+                        panel.y + point.y * panel.height,
+                    )
+                });
+
+                frame.stroke(
+                    &canvas::Path::line(from, to),
+                    canvas::Stroke::default()
+                        .with_color(Color::from_rgb(1.0, 0.6, 0.0))
+                        .with_width(1.5),
+                );
+            }
+        });
+
+        vec![frame.into_geometry()]
+    }
+}
+
+/// Dim everything on `panel` outside `selection`, above, below, left and
+/// right of it.
+fn dim_around(frame: &mut canvas::Frame, panel: Rectangle, selection: Rectangle) {
+    let (left, top) = (
+        panel.x + selection.x * panel.width,
+        panel.y + selection.y * panel.height,
+    );
+    let (right, bottom) = (
+        left + selection.width * panel.width,
+        top + selection.height * panel.height,
+    );
+    let dim = Color::from_rgba(0.0, 0.0, 0.0, 0.4);
+
+    for (x, y, width, height) in [
+        (panel.x, panel.y, panel.width, top - panel.y),
+        (
+            panel.x,
+            bottom,
+            panel.width,
+            panel.y + panel.height - bottom,
+        ),
+        (panel.x, top, left - panel.x, bottom - top),
+        (right, top, panel.x + panel.width - right, bottom - top),
+    ] {
+        frame.fill_rectangle(Point::new(x, y), Size::new(width, height), dim);
+    }
+}
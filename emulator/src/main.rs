@@ -0,0 +1,66 @@
+//! Pretends to be a kindle, so the desktop viewer can be worked on without
+//! one. It pairs and streams exactly like the real client, but the frames
+//! come from a script instead of the screen.
+
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{Context, bail};
+
+use crate::script::{Player, Script};
+
+mod script;
+
+const USAGE: &str = "usage: kinshare-emulator <script.json> | kinshare-emulator pair";
+
+fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+
+    let dir = match std::env::var_os("KINSHARE_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => default_dir()?,
+    };
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("failed to create '{}'", dir.display()))?;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(async {
+        match args.as_slice() {
+            [command] if command == "pair" => kinshare_client::pair(Some(&dir)).await,
+            [path] => {
+                let script = Arc::new(Script::load(path.as_ref()).await?);
+
+                println!("Keeping keys and stream.json in {}", dir.display());
+
+                kinshare_client::run(kinshare_client::Options {
+                    source: Some(Box::new(move || {
+                        Ok(Box::new(Player::new(Arc::clone(&script))))
+                    })),
+                    input: false,
+                    config: None,
+                    dir: Some(dir.clone()),
+                })
+                .await
+            }
+            _ => bail!("{USAGE}"),
+        }
+    })
+}
+
+/// Where keys and `stream.json` live unless `KINSHARE_DIR` says otherwise,
+/// the user's data directory so it's the same wherever this is started.
+fn default_dir() -> anyhow::Result<PathBuf> {
+    let data = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(|home| PathBuf::from(home).join(".local/share"))
+        })
+        .context("no data directory to keep keys in, set KINSHARE_DIR")?;
+
+    Ok(data.join("kinshare-emulator"))
+}
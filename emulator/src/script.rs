@@ -0,0 +1,374 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, ensure};
+use font8x8::UnicodeFonts;
+use kinshare_client::{FrameSource, Image};
+use kinshare_server::Recording;
+use kinshare_shared::{
+    codec,
+    messages::{self, Rotation},
+};
+use serde::Deserialize;
+
+/// What the emulated kindle shows, loaded from a JSON file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptConfig {
+    /// Size of the emulated panel.
+    width: u32,
+    height: u32,
+    /// Start over after the last step, on by default.
+    repeat: Option<bool>,
+    steps: Vec<StepConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum StepConfig {
+    /// A PNG or binary PGM, drawn from the top left corner.
+    Image {
+        path: PathBuf,
+        /// Seconds the step stays up, 1 by default.
+        duration: Option<f64>,
+    },
+    /// Black text on white, wrapped to fit the screen.
+    Text {
+        text: String,
+        /// Size of a font pixel in screen pixels, 4 by default.
+        scale: Option<u32>,
+        duration: Option<f64>,
+    },
+    /// The whole screen in one gray level.
+    Fill { gray: u8, duration: Option<f64> },
+    /// Every frame of a recording made with `kinshare-desktop --record`, at
+    /// the pace it was recorded.
+    Recording { path: PathBuf },
+}
+
+/// A loaded script, with every image drawn ahead of time. Shared by all
+/// connections, each plays it from the start.
+pub(crate) struct Script {
+    width: u32,
+    height: u32,
+    repeat: bool,
+    steps: Vec<Step>,
+}
+
+enum Step {
+    Still { image: Image, duration: Duration },
+    Recording(Recording),
+}
+
+impl Script {
+    /// Load the script at `path`, relative paths in it are relative to the
+    /// script itself.
+    pub(crate) async fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("failed to read '{}'", path.display()))?;
+        let config: ScriptConfig = serde_json::from_slice(&data)
+            .with_context(|| format!("invalid '{}'", path.display()))?;
+
+        ensure!(
+            config.width > 0 && config.height > 0,
+            "screen size can't be zero"
+        );
+        ensure!(!config.steps.is_empty(), "script has no steps");
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let (width, height) = (config.width, config.height);
+        let mut steps = Vec::with_capacity(config.steps.len());
+
+        for step in config.steps {
+            let duration = |duration: Option<f64>| -> anyhow::Result<Duration> {
+                Duration::try_from_secs_f64(duration.unwrap_or(1.0))
+                    .context("step durations can't be negative")
+            };
+
+            steps.push(match step {
+                StepConfig::Image { path, duration: d } => {
+                    let image = Image::load(&dir.join(path))?;
+                    let mut canvas = blank(width, height, 0xff);
+                    blit(&mut canvas, &image.pixels, image.width, image.height);
+
+                    Step::Still {
+                        image: canvas,
+                        duration: duration(d)?,
+                    }
+                }
+                StepConfig::Text {
+                    text,
+                    scale,
+                    duration: d,
+                } => {
+                    let scale = scale.unwrap_or(4);
+                    ensure!(scale > 0, "text scale can't be zero");
+
+                    Step::Still {
+                        image: render_text(&text, scale, width, height),
+                        duration: duration(d)?,
+                    }
+                }
+                StepConfig::Fill { gray, duration: d } => Step::Still {
+                    image: blank(width, height, gray),
+                    duration: duration(d)?,
+                },
+                StepConfig::Recording { path } => {
+                    let recording = Recording::open(&dir.join(&path)).await?;
+                    ensure!(!recording.is_empty(), "'{}' has no frames", path.display());
+
+                    Step::Recording(recording)
+                }
+            });
+        }
+
+        Ok(Self {
+            width,
+            height,
+            repeat: config.repeat.unwrap_or(true),
+            steps,
+        })
+    }
+}
+
+/// Plays a [`Script`] as a [`FrameSource`], moving on to the next step once
+/// the current one is over.
+pub(crate) struct Player {
+    script: Arc<Script>,
+    current: usize,
+    started: Instant,
+    /// Where recordings are drawn, sized like the script's screen.
+    canvas: Image,
+    playback: Option<Playback>,
+}
+
+/// Decoding state of the recording being played.
+struct Playback {
+    framebuffer: Arc<Mutex<Box<[u8]>>>,
+    encode_buffer: Box<[u8]>,
+    decode_buffer: Box<[u8]>,
+    rotation: Rotation,
+    /// Index of the next frame to decode.
+    next: usize,
+}
+
+impl Player {
+    pub(crate) fn new(script: Arc<Script>) -> Self {
+        let canvas = blank(script.width, script.height, 0xff);
+
+        let mut player = Self {
+            script,
+            current: 0,
+            started: Instant::now(),
+            canvas,
+            playback: None,
+        };
+
+        player.enter(0);
+        player
+    }
+
+    fn enter(&mut self, step: usize) {
+        self.current = step;
+        self.started = Instant::now();
+
+        self.playback = match &self.script.steps[step] {
+            Step::Still { .. } => None,
+            Step::Recording(recording) => {
+                let info = &recording.info;
+
+                Some(Playback {
+                    framebuffer: Arc::new(Mutex::new(
+                        vec![0; info.display_size()].into_boxed_slice(),
+                    )),
+                    encode_buffer: vec![0; codec::max_encoded_len(info.chunk_size())]
+                        .into_boxed_slice(),
+                    decode_buffer: vec![0; info.chunk_size()].into_boxed_slice(),
+                    rotation: Rotation::default(),
+                    next: 0,
+                })
+            }
+        };
+    }
+
+    fn finished(&self) -> bool {
+        match (&self.script.steps[self.current], &self.playback) {
+            (Step::Still { duration, .. }, _) => self.started.elapsed() >= *duration,
+            (Step::Recording(recording), Some(playback)) => playback.next == recording.len(),
+            (Step::Recording(_), None) => true,
+        }
+    }
+}
+
+impl FrameSource for Player {
+    fn size(&self) -> (u32, u32) {
+        (self.script.width, self.script.height)
+    }
+
+    fn advance(&mut self) -> anyhow::Result<Rotation> {
+        if self.finished() {
+            let next = self.current + 1;
+
+            if next < self.script.steps.len() {
+                self.enter(next);
+            } else if self.script.repeat {
+                self.enter(0);
+            }
+        }
+
+        let (Step::Recording(recording), Some(playback)) =
+            (&self.script.steps[self.current], &mut self.playback)
+        else {
+            return Ok(Rotation::Upright);
+        };
+
+        let elapsed = self.started.elapsed();
+        let mut decoded = false;
+
+        while playback.next < recording.len() && recording.frame(playback.next).0 <= elapsed {
+            let (_, mut frame) = recording.frame(playback.next);
+
+            // Frames are already in memory, so reading them never waits.
+            playback.rotation = futures_lite::future::block_on(messages::read_frame(
+                &recording.info,
+                &mut frame,
+                &mut playback.encode_buffer,
+                &mut playback.decode_buffer,
+                &playback.framebuffer,
+            ))
+            .with_context(|| format!("bad frame {} in recording", playback.next))?
+            .rotation;
+
+            playback.next += 1;
+            decoded = true;
+        }
+
+        if decoded {
+            let info = &recording.info;
+
+            self.canvas.pixels.fill(0xff);
+            blit(
+                &mut self.canvas,
+                &playback.framebuffer.lock().unwrap(),
+                info.display_width as u32,
+                info.display_height as u32,
+            );
+        }
+
+        Ok(playback.rotation)
+    }
+
+    fn copy_rows(&self, rows: Range<usize>, width: usize, out: &mut [u8]) {
+        match &self.script.steps[self.current] {
+            Step::Still { image, .. } => image.copy_rows(rows, width, out),
+            Step::Recording(_) => self.canvas.copy_rows(rows, width, out),
+        }
+    }
+}
+
+fn blank(width: u32, height: u32, gray: u8) -> Image {
+    Image {
+        width,
+        height,
+        pixels: vec![gray; width as usize * height as usize],
+    }
+}
+
+/// Draw `pixels` into the top left corner of `canvas`, cutting off whatever
+/// doesn't fit.
+fn blit(canvas: &mut Image, pixels: &[u8], width: u32, height: u32) {
+    let (width, height) = (width as usize, height as usize);
+    let stride = canvas.width as usize;
+    let copy_width = width.min(stride);
+
+    for (row, source) in pixels
+        .chunks_exact(width)
+        .take(height.min(canvas.height as usize))
+        .enumerate()
+    {
+        canvas.pixels[row * stride..row * stride + copy_width]
+            .copy_from_slice(&source[..copy_width]);
+    }
+}
+
+/// Lay `text` out in the 8x8 font, breaking lines between words where it
+/// can. Characters the font doesn't have come out as `?`.
+fn render_text(text: &str, scale: u32, width: u32, height: u32) -> Image {
+    let mut image = blank(width, height, 0xff);
+    let glyph_size = 8 * scale as usize;
+    let margin = glyph_size;
+    let columns = (width as usize).saturating_sub(2 * margin) / glyph_size;
+
+    if columns == 0 {
+        return image;
+    }
+
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+
+        for word in paragraph.split(' ') {
+            let mut word = word.to_owned();
+
+            // Words too long for a whole line get split wherever they hit the
+            // edge.
+            while word.chars().count() > columns {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+
+                let split = word.char_indices().nth(columns).unwrap().0;
+                lines.push(word[..split].to_owned());
+                word = word[split..].to_owned();
+            }
+
+            let len = line.chars().count();
+
+            if len > 0 && len + 1 + word.chars().count() > columns {
+                lines.push(std::mem::take(&mut line));
+            } else if len > 0 {
+                line.push(' ');
+            }
+
+            line.push_str(&word);
+        }
+
+        lines.push(line);
+    }
+
+    let stride = width as usize;
+
+    for (row, line) in lines.iter().enumerate() {
+        let top = margin + row * (glyph_size + 2 * scale as usize);
+
+        if top + glyph_size > height as usize {
+            break;
+        }
+
+        for (column, c) in line.chars().enumerate() {
+            let glyph = font8x8::BASIC_FONTS
+                .get(c)
+                .or_else(|| font8x8::BASIC_FONTS.get('?'))
+                .unwrap_or_default();
+            let left = margin + column * glyph_size;
+
+            for y in 0..glyph_size {
+                let bits = glyph[y / scale as usize];
+
+                for x in 0..glyph_size {
+                    if bits >> (x / scale as usize) & 1 == 1 {
+                        image.pixels[(top + y) * stride + left + x] = 0x00;
+                    }
+                }
+            }
+        }
+    }
+
+    image
+}
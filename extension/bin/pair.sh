@@ -0,0 +1,13 @@
+#!/bin/sh
+
+KINSHARE=/mnt/us/extensions/kinshare
+
+pkill kinshare-client 2>/dev/null || true
+
+# The client shows the code itself and starts streaming once paired.
+nohup sh -c "'$KINSHARE/bin/kinshare-client' pair && exec '$KINSHARE/bin/kinshare-client'" >> "$KINSHARE/logs.txt" 2>&1 &
+PID=$!
+
+if ! kill -0 "$PID" 2>/dev/null; then
+    eips 3 3 "Kinshare pairing failed to start, check log"
+fi
@@ -0,0 +1,35 @@
+#![no_main]
+
+use std::sync::{Arc, Mutex};
+
+use futures_lite::future;
+use kinshare_shared::{codec, messages};
+use libfuzzer_sys::fuzz_target;
+
+/// Larger panels are valid but slow every run down without finding more.
+const MAX_DISPLAY_SIZE: usize = 1 << 20;
+
+// The input starts with the stream info, so the fuzzer picks the grid the
+// frames after it are decoded against.
+fuzz_target!(|data: &[u8]| {
+    let mut data = data;
+
+    future::block_on(async {
+        let Ok(info) = messages::read_info(&mut data).await else {
+            return;
+        };
+
+        if info.display_size() > MAX_DISPLAY_SIZE {
+            return;
+        }
+
+        let framebuffer = Arc::new(Mutex::new(vec![0; info.display_size()].into_boxed_slice()));
+        let mut encoded = vec![0; codec::max_encoded_len(info.chunk_size())];
+        let mut decoded = vec![0; info.chunk_size()];
+
+        while messages::read_frame(&info, &mut data, &mut encoded, &mut decoded, &framebuffer)
+            .await
+            .is_ok()
+        {}
+    });
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use futures_lite::future;
+use kinshare_shared::messages;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut data = data;
+
+    // Slices never have to wait for more data, so this can't block.
+    let _ = future::block_on(messages::read_info(&mut data));
+});
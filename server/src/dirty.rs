@@ -0,0 +1,120 @@
+use kinshare_shared::messages::{Info, Rect};
+
+/// Chunks of a stream's framebuffer that changed since it was last drawn,
+/// collected from [`crate::DeviceMessage::Updated`] so a viewer only has to
+/// copy those.
+#[derive(Debug)]
+pub struct DirtyChunks {
+    info: Info,
+    chunks: Vec<bool>,
+    count: usize,
+}
+
+impl DirtyChunks {
+    /// Everything starts out dirty, nothing of a new framebuffer has been
+    /// drawn yet.
+    pub fn new(info: &Info) -> Self {
+        Self {
+            info: info.clone(),
+            chunks: vec![true; info.chunk_count()],
+            count: info.chunk_count(),
+        }
+    }
+
+    /// Mark the chunks at these grid coordinates. Ones outside the grid are
+    /// ignored.
+    pub fn mark(&mut self, chunks: &[(u8, u8)]) {
+        for &(x, y) in chunks {
+            let (x, y) = (x as usize, y as usize);
+
+            if x < self.info.chunks_per_x
+                && let Some(chunk) = self.chunks.get_mut(y * self.info.chunks_per_x + x)
+                && !*chunk
+            {
+                *chunk = true;
+                self.count += 1;
+            }
+        }
+    }
+
+    pub fn mark_all(&mut self) {
+        self.chunks.fill(true);
+        self.count = self.chunks.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Pixel rectangles covering every dirty chunk, leaving none dirty.
+    /// Neighbouring chunks are merged, runs along a row first and then runs
+    /// spanning the same columns in consecutive rows, and past half the
+    /// screen it's just the whole screen.
+    pub fn take(&mut self) -> Vec<Rect> {
+        let (columns, rows) = (self.info.chunks_per_x, self.info.chunks_per_y);
+
+        let rects = if self.count == 0 {
+            Vec::new()
+        } else if self.count * 2 > self.chunks.len() {
+            vec![Rect {
+                x: 0,
+                y: 0,
+                width: self.info.display_width,
+                height: self.info.display_height,
+            }]
+        } else {
+            let mut rects = Vec::new();
+            // Runs of the row above as first and past-the-end column, with
+            // everything they cover so far.
+            let mut open = Vec::<(usize, usize, Rect)>::new();
+
+            for y in 0..rows {
+                let row = &self.chunks[y * columns..(y + 1) * columns];
+                let mut runs = Vec::new();
+                let mut x = 0;
+
+                while x < columns {
+                    if !row[x] {
+                        x += 1;
+                        continue;
+                    }
+
+                    let start = x;
+                    while x < columns && row[x] {
+                        x += 1;
+                    }
+
+                    let first = self.info.chunk_rect(start, y);
+
+                    let rect = match open.iter().position(|run| (run.0, run.1) == (start, x)) {
+                        Some(index) => {
+                            let (_, _, rect) = open.swap_remove(index);
+
+                            Rect {
+                                height: rect.height + first.height,
+                                ..rect
+                            }
+                        }
+                        None => Rect {
+                            width: self.info.chunk_rect(x - 1, y).right() - first.x,
+                            ..first
+                        },
+                    };
+
+                    runs.push((start, x, rect));
+                }
+
+                rects.extend(open.drain(..).map(|(_, _, rect)| rect));
+                open = runs;
+            }
+
+            rects.extend(open.into_iter().map(|(_, _, rect)| rect));
+            rects
+        };
+
+        self.chunks.fill(false);
+        self.count = 0;
+
+        rects
+    }
+}
@@ -1,48 +1,220 @@
 use std::{
+    collections::HashSet,
+    io,
+    path::PathBuf,
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use anyhow::{Context, bail};
 use iroh::{
-    Endpoint, SecretKey,
-    endpoint::{Connection, QuicTransportConfig, RecvStream, presets},
+    Endpoint, EndpointAddr, EndpointId, SecretKey,
+    endpoint::{Connection, QuicTransportConfig, presets},
 };
-use iroh_mdns_address_lookup::MdnsAddressLookup;
-use kinshare_shared::{consts::ALPN, messages};
-use tokio::{fs, sync::mpsc};
+use iroh_mdns_address_lookup::{DiscoveryEvent, MdnsAddressLookup};
+use kinshare_shared::{
+    codec,
+    consts::{ALPN, PAIRING_ALPN, PAIRING_SERVICE},
+    messages::{
+        self, Capabilities, HandshakeError, Hello, InputEvent, ProtocolError, Rotation, Settings,
+        Update,
+    },
+    pairing::{self, Commitment, Role},
+    transport::{Direct, Reader, Writer},
+};
+use n0_future::StreamExt;
+use tokio::{fs, sync::mpsc, task::JoinSet, time};
+
+use crate::{
+    recording::{Recorder, Tee},
+    stats::Counted,
+};
+
+mod dirty;
+mod recording;
+mod replay;
+mod screenshot;
+mod stats;
+
+pub use dirty::DirtyChunks;
+pub use recording::Recording;
+pub use replay::replay;
+pub use screenshot::{Difference, ImageFormat, Screenshot};
+pub use stats::{FrameStats, LinkStats};
+
+const CAPABILITIES: Capabilities = Capabilities::CODEC_LZ4
+    .union(Capabilities::CODEC_GRAY4)
+    .union(Capabilities::CODEC_RLE)
+    .union(Capabilities::CODEC_ZSTD)
+    .union(Capabilities::FORMAT_GRAY8)
+    .union(Capabilities::INPUT)
+    .union(Capabilities::DELTA)
+    .union(Capabilities::RECONFIGURE);
+
+/// Kindles are told apart by their endpoint id.
+pub type DeviceId = EndpointId;
 
 #[derive(Debug, Clone)]
 pub enum Message {
     Message(&'static str),
+    /// Where to send [`Command`]s, sent once at startup.
+    Ready(mpsc::UnboundedSender<Command>),
+    /// A kindle accepted a pairing code and was added to the known devices.
+    Paired(DeviceId),
+    PairingFailed(String),
+    /// Where playback is, sent while replaying a recording.
+    Replay(ReplayStatus),
+    /// Every known kindle, sent once before any [`Message::Device`].
+    Devices(Vec<DeviceId>),
+    Device(DeviceId, DeviceMessage),
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Pair with the kindle currently showing `code`.
+    Pair { code: u32 },
+    /// Replay only, pause or resume playback.
+    Pause(bool),
+    /// Replay only, jump to a position in the recording.
+    Seek(Duration),
+    /// Replay only, playback speed relative to real time.
+    Speed(f64),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayStatus {
+    pub position: Duration,
+    pub length: Duration,
+    pub paused: bool,
+    pub speed: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Record every stream into this directory.
+    pub record_dir: Option<PathBuf>,
+    /// Stream from the one kindle listening here instead of every paired one
+    /// over iroh.
+    pub direct: Option<Direct>,
+}
+
+#[derive(Debug, Clone)]
+pub enum DeviceMessage {
+    Status(&'static str),
+    /// The kindle speaks a protocol we can't talk to, it needs updating.
+    Incompatible(HandshakeError),
     Connected {
         info: messages::Info,
         framebuffer: Arc<Mutex<Box<[u8]>>>,
+        /// Forwards input to the kindle, if it supports injecting it.
+        input: Option<mpsc::UnboundedSender<InputEvent>>,
+        /// Asks the kindle to switch to new settings, if it knows how.
+        reconfigure: Option<mpsc::UnboundedSender<Settings>>,
+    },
+    /// The kindle switched to new settings. Frames from now on go into the
+    /// new `framebuffer`, which starts out blank.
+    Reconfigured {
+        info: messages::Info,
+        framebuffer: Arc<Mutex<Box<[u8]>>>,
+    },
+    Rotated(Rotation),
+    /// How the frame about to be reported as [`DeviceMessage::Updated`] went.
+    Stats(FrameStats),
+    /// The framebuffer changed. `chunks` are the grid coordinates of the
+    /// chunks that did, see [`DirtyChunks`].
+    Updated {
+        chunks: Vec<(u8, u8)>,
     },
-    Updated,
     Closed,
 }
 
-pub async fn run(sender: mpsc::UnboundedSender<Message>) -> anyhow::Result<()> {
-    let (server_key, kindle_key) = if let Ok(bytes) = fs::read("connection.keys").await {
-        (
-            SecretKey::from_bytes(&bytes[..32].try_into()?),
-            SecretKey::from_bytes(&bytes[32..64].try_into()?),
-        )
-    } else {
-        let server_key = SecretKey::generate();
-        let kindle_key = SecretKey::generate();
+/// Tags everything sent through it with the device it's about.
+#[derive(Clone)]
+struct DeviceSender {
+    device: DeviceId,
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl DeviceSender {
+    fn send(&self, message: DeviceMessage) -> anyhow::Result<()> {
+        self.sender.send(Message::Device(self.device, message))?;
+
+        Ok(())
+    }
+}
+
+const KEY_PATH: &str = "server.key";
+const DEVICES_PATH: &str = "devices.keys";
 
-        let data = [server_key.to_bytes(), kindle_key.to_bytes()].concat();
+/// How long to look for a kindle that accepts the code typed in.
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(30);
 
-        fs::write("connection.keys", data).await?;
+/// Our own secret key, generated on first run. Kindles learn its public half
+/// while pairing.
+async fn load_key() -> anyhow::Result<SecretKey> {
+    match fs::read(KEY_PATH).await {
+        Ok(bytes) => {
+            let bytes = bytes.try_into().map_err(|bytes: Vec<u8>| {
+                anyhow::anyhow!(
+                    "'{KEY_PATH}' is corrupt, expected 32 bytes but got {}",
+                    bytes.len()
+                )
+            })?;
 
-        sender.send(
-            Message::Message("Wrote connection information to 'connection.keys'. Share it with the kindle in '/mnt/us/extensions/kinshare/connection.keys'.")
-        )?;
+            Ok(SecretKey::from_bytes(&bytes))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let key = SecretKey::generate();
+
+            fs::write(KEY_PATH, key.to_bytes())
+                .await
+                .with_context(|| format!("failed to write '{KEY_PATH}'"))?;
+
+            Ok(key)
+        }
+        Err(err) => Err(err).with_context(|| format!("failed to read '{KEY_PATH}'")),
+    }
+}
 
-        (server_key, kindle_key)
+/// Public keys of every paired kindle, 32 bytes each.
+async fn load_devices() -> anyhow::Result<Vec<DeviceId>> {
+    let bytes = match fs::read(DEVICES_PATH).await {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read '{DEVICES_PATH}'")),
     };
 
+    anyhow::ensure!(
+        bytes.len() % 32 == 0,
+        "'{DEVICES_PATH}' is corrupt, expected a multiple of 32 bytes but got {}",
+        bytes.len()
+    );
+
+    bytes
+        .chunks_exact(32)
+        .map(|key| Ok(DeviceId::from_bytes(key.try_into()?)?))
+        .collect()
+}
+
+async fn save_devices(devices: &[DeviceId]) -> anyhow::Result<()> {
+    let bytes = devices
+        .iter()
+        .flat_map(|device| *device.as_bytes())
+        .collect::<Vec<u8>>();
+
+    fs::write(DEVICES_PATH, bytes)
+        .await
+        .with_context(|| format!("failed to write '{DEVICES_PATH}'"))
+}
+
+pub async fn run(sender: mpsc::UnboundedSender<Message>, options: Options) -> anyhow::Result<()> {
+    if let Some(direct) = options.direct.clone() {
+        return run_direct(direct, sender, options).await;
+    }
+
+    let server_key = load_key().await?;
+    let mut devices = load_devices().await?;
+
     let endpoint = Endpoint::builder(presets::N0)
         .secret_key(server_key)
         .address_lookup(MdnsAddressLookup::builder())
@@ -57,84 +229,543 @@ pub async fn run(sender: mpsc::UnboundedSender<Message>) -> anyhow::Result<()> {
 
     println!("Endpoint id: {}", endpoint.id().to_z32());
 
-    sender.send(Message::Message("Connecting..."))?;
+    // Kindles waiting to be paired advertise under their own service name, so
+    // they don't show up as regular peers.
+    let pairing = MdnsAddressLookup::builder()
+        .service_name(PAIRING_SERVICE)
+        .advertise(false)
+        .build(endpoint.id())?;
+
+    let (commands, mut command_receiver) = mpsc::unbounded_channel();
+
+    sender.send(Message::Ready(commands))?;
+    sender.send(Message::Devices(devices.clone()))?;
+
+    if devices.is_empty() {
+        sender.send(Message::Message(
+            "No kindles paired yet. Pick 'Pair Kinshare' in KUAL on the kindle and enter the code it shows.",
+        ))?;
+    }
+
+    let mut tasks = JoinSet::new();
+    let mut pairings = JoinSet::new();
+
+    let spawn_device = |tasks: &mut JoinSet<_>, device| {
+        tasks.spawn(run_device(
+            endpoint.clone(),
+            device,
+            sender.clone(),
+            options.clone(),
+        ));
+    };
+
+    for &device in &devices {
+        spawn_device(&mut tasks, device);
+    }
+
+    loop {
+        tokio::select! {
+            Some(result) = tasks.join_next() => result??,
+            Some(command) = command_receiver.recv() => match command {
+                Command::Pair { code } => {
+                    pairings.spawn(pair(endpoint.clone(), pairing.clone(), code));
+                }
+                Command::Pause(_) | Command::Seek(_) | Command::Speed(_) => {}
+            },
+            Some(result) = pairings.join_next() => match result? {
+                Ok(device) => {
+                    // Re-pairing a known kindle just refreshes its side.
+                    let new = !devices.contains(&device);
+
+                    if new {
+                        devices.push(device);
+                        save_devices(&devices).await?;
+                    }
+
+                    sender.send(Message::Paired(device))?;
+
+                    if new {
+                        spawn_device(&mut tasks, device);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Error pairing: {err:#}");
+                    sender.send(Message::PairingFailed(format!("{err:#}")))?;
+                }
+            },
+            else => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Stream from the kindle at `direct`. There's nothing to pair with, so the
+/// only device is the one on the command line.
+async fn run_direct(
+    direct: Direct,
+    sender: mpsc::UnboundedSender<Message>,
+    options: Options,
+) -> anyhow::Result<()> {
+    let device = direct_device(&direct);
+    let (commands, mut command_receiver) = mpsc::unbounded_channel();
+
+    sender.send(Message::Ready(commands))?;
+    sender.send(Message::Devices(vec![device]))?;
+
+    let streaming = run_direct_device(direct, device, sender.clone(), options);
+    tokio::pin!(streaming);
 
     loop {
-        let Ok(connection) = endpoint.connect(kindle_key.public(), ALPN).await else {
-            sender.send(Message::Message("Retrying..."))?;
-            continue;
+        tokio::select! {
+            result = &mut streaming => return result,
+            Some(command) = command_receiver.recv() => match command {
+                Command::Pair { .. } => sender.send(Message::PairingFailed(
+                    "direct connections don't need pairing".to_owned(),
+                ))?,
+                Command::Pause(_) | Command::Seek(_) | Command::Speed(_) => {}
+            },
+        }
+    }
+}
+
+/// Direct connections have no key to tell kindles apart by, so make up an id
+/// from the address instead, the same one every run.
+fn direct_device(direct: &Direct) -> DeviceId {
+    let seed = blake3::derive_key("kinshare direct device", direct.to_string().as_bytes());
+
+    SecretKey::from_bytes(&seed).public()
+}
+
+/// Offer `code` to every kindle in pairing mode until one of them accepts it.
+async fn pair(
+    endpoint: Endpoint,
+    pairing: MdnsAddressLookup,
+    code: u32,
+) -> anyhow::Result<DeviceId> {
+    let mut events = pairing.subscribe().await;
+    let mut tried = HashSet::new();
+
+    let search = async {
+        while let Some(event) = events.next().await {
+            let DiscoveryEvent::Discovered { endpoint_info, .. } = event else {
+                continue;
+            };
+
+            let device = endpoint_info.endpoint_id;
+
+            // Every wrong guess counts against the kindle's attempts.
+            if !tried.insert(device) {
+                continue;
+            }
+
+            match offer_code(&endpoint, endpoint_info.into(), code).await {
+                Ok(true) => return Ok(device),
+                Ok(false) => println!("{device} has a different pairing code"),
+                Err(err) => eprintln!("Error pairing with {device}: {err:#}"),
+            }
+        }
+
+        bail!("stopped looking for kindles")
+    };
+
+    time::timeout(PAIRING_TIMEOUT, search)
+        .await
+        .unwrap_or_else(|_| {
+            if tried.is_empty() {
+                bail!("no kindle in pairing mode found on the local network")
+            } else {
+                bail!("no kindle accepted the code, check it and try again")
+            }
+        })
+}
+
+/// Pair with the kindle at `addr` if it proves it knows `code`, and proving
+/// we do in turn. See [`kinshare_shared::pairing`].
+async fn offer_code(endpoint: &Endpoint, addr: EndpointAddr, code: u32) -> anyhow::Result<bool> {
+    let connection = endpoint.connect(addr, PAIRING_ALPN).await?;
+    let (desktop, kindle) = (endpoint.id(), connection.remote_id());
+    let (mut send, mut recv) = connection.open_bi().await?;
+
+    let commitment = Commitment::new(pairing::proof(
+        code,
+        Role::Desktop,
+        desktop.as_bytes(),
+        kindle.as_bytes(),
+    ))?;
+
+    messages::write_pairing_commitment(&mut send, &commitment.hash()).await?;
+
+    let proof = messages::read_pairing_proof(&mut recv).await?;
+    let expected = pairing::proof(code, Role::Kindle, desktop.as_bytes(), kindle.as_bytes());
+
+    // Whatever it is, it doesn't know the code, and doesn't get our proof to
+    // work it out from.
+    if !pairing::proofs_match(&proof, &expected) {
+        connection.close(0u8.into(), b"Wrong code");
+        return Ok(false);
+    }
+
+    messages::write_pairing_opening(&mut send, &commitment).await?;
+    send.finish()?;
+
+    let accepted = messages::read_pairing_result(&mut recv).await?;
+
+    connection.close(0u8.into(), &[]);
+
+    Ok(accepted)
+}
+
+/// Keep connecting to `device` and streaming from it, reconnecting whenever
+/// the stream ends. Only returns once `sender` is closed.
+pub async fn run_device(
+    endpoint: Endpoint,
+    device: DeviceId,
+    sender: mpsc::UnboundedSender<Message>,
+    options: Options,
+) -> anyhow::Result<()> {
+    let sender = DeviceSender { device, sender };
+
+    sender.send(DeviceMessage::Status("Connecting..."))?;
+
+    let mut backoff = Backoff::default();
+
+    loop {
+        let connection = match endpoint.connect(sender.device, ALPN).await {
+            Ok(connection) => connection,
+            Err(err) => {
+                let error = format!("Error connecting to {}: {err:#}", sender.device);
+                backoff.failed(&sender, error).await?;
+                continue;
+            }
         };
 
         println!("Connected to {}", connection.remote_id());
 
-        match Stream::new(&sender, &connection).await {
-            Ok(stream) => {
-                if let Err(err) = stream.run().await {
-                    eprintln!("Error running stream: {err:#?}");
-                }
+        match connection.accept_bi().await {
+            Ok((control, stream)) => {
+                let reader = Box::new(stream);
+                let writer = Box::new(control);
+
+                session(
+                    &sender,
+                    &options,
+                    &mut backoff,
+                    reader,
+                    writer,
+                    Some(&connection),
+                )
+                .await?;
             }
             Err(err) => {
-                eprintln!("Error initializing stream: {err:#?}");
+                let error = format!("Error initializing stream: {err:#}");
+                backoff.failed(&sender, error).await?;
             }
         }
 
         connection.close(0u8.into(), &[]);
-        sender.send(Message::Closed)?;
+        sender.send(DeviceMessage::Closed)?;
+    }
+}
+
+/// Like [`run_device`], for a kindle listening on `direct`.
+async fn run_direct_device(
+    direct: Direct,
+    device: DeviceId,
+    sender: mpsc::UnboundedSender<Message>,
+    options: Options,
+) -> anyhow::Result<()> {
+    let sender = DeviceSender { device, sender };
+
+    sender.send(DeviceMessage::Status("Connecting..."))?;
+
+    let mut backoff = Backoff::default();
+
+    loop {
+        let (reader, writer) = match direct.connect().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                let error = format!("Error connecting to {direct}: {err}");
+                backoff.failed(&sender, error).await?;
+                continue;
+            }
+        };
+
+        println!("Connected to {direct}");
+
+        session(&sender, &options, &mut backoff, reader, writer, None).await?;
+        sender.send(DeviceMessage::Closed)?;
+    }
+}
+
+/// Shortest and longest wait between attempts to reach a kindle.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Spaces out attempts to reach a kindle that's offline, doubling the wait
+/// after every failure, so a switched off kindle doesn't keep a core busy.
+#[derive(Debug, Default)]
+struct Backoff {
+    /// How long was waited after the last failure, `None` while streaming.
+    delay: Option<Duration>,
+}
+
+impl Backoff {
+    /// Wait before the next attempt. Only the first failure of an outage is
+    /// reported, the rest would say the same thing.
+    async fn failed(&mut self, sender: &DeviceSender, error: String) -> anyhow::Result<()> {
+        let delay = match self.delay {
+            Some(delay) => (delay * 2).min(MAX_BACKOFF),
+            None => {
+                eprintln!("{error}");
+                sender.send(DeviceMessage::Status("Retrying..."))?;
+
+                MIN_BACKOFF
+            }
+        };
+
+        self.delay = Some(delay);
+        time::sleep(delay).await;
+
+        Ok(())
+    }
+
+    /// The kindle is streaming, whatever goes wrong next is a new outage.
+    fn connected(&mut self) {
+        self.delay = None;
+    }
+}
+
+/// Stream from one connection until it breaks. Only fails if `sender` is
+/// closed, everything else is reported and left for the caller to reconnect.
+/// A kindle that takes the connection but never streams counts as still out
+/// for `backoff`.
+async fn session(
+    sender: &DeviceSender,
+    options: &Options,
+    backoff: &mut Backoff,
+    reader: Reader,
+    writer: Writer,
+    connection: Option<&Connection>,
+) -> anyhow::Result<()> {
+    match Stream::new(sender, options, reader, writer, connection).await {
+        Ok(stream) => {
+            backoff.connected();
+
+            if let Err(err) = stream.run().await {
+                eprintln!("Error running stream: {err:#?}");
+            }
+        }
+        Err(err) => {
+            let handshake = match err.downcast_ref::<ProtocolError>() {
+                Some(ProtocolError::Handshake(err)) => Some(err),
+                _ => err.downcast_ref::<HandshakeError>(),
+            };
+
+            if let Some(err) = handshake {
+                eprintln!("Error initializing stream: {err:#?}");
+                sender.send(DeviceMessage::Incompatible(err.clone()))?;
+
+                // Reconnecting right away would just fail the same way.
+                time::sleep(Duration::from_secs(5)).await;
+            } else {
+                let error = format!("Error initializing stream: {err:#}");
+                backoff.failed(sender, error).await?;
+            }
+        }
     }
+
+    Ok(())
 }
 
 struct Stream<'a> {
-    sender: &'a mpsc::UnboundedSender<Message>,
+    sender: &'a DeviceSender,
     info: messages::Info,
-    stream: RecvStream,
+    stream: Counted<Reader>,
+    /// Sampled for [`LinkStats`] when streaming over iroh.
+    connection: Option<Connection>,
     framebuffer: Arc<Mutex<Box<[u8]>>>,
     encode_buffer: Box<[u8]>,
     decode_buffer: Box<[u8]>,
+    rotation: Rotation,
+    recorder: Option<Recorder>,
+    /// Where a new recording starts whenever the kindle is reconfigured.
+    record_dir: Option<PathBuf>,
+    /// The current frame as received, kept for the recorder.
+    frame: Vec<u8>,
+    /// When the last frame came in.
+    last_frame: Option<Instant>,
 }
 
 impl<'a> Stream<'a> {
     async fn new(
-        sender: &'a mpsc::UnboundedSender<Message>,
-        connection: &Connection,
+        sender: &'a DeviceSender,
+        options: &Options,
+        mut stream: Reader,
+        mut control: Writer,
+        connection: Option<&Connection>,
     ) -> anyhow::Result<Self> {
-        let mut stream = connection.accept_uni().await?;
+        let hello = messages::read_hello(&mut stream).await?;
+        // Always answer, so the kindle can report a mismatch too.
+        messages::write_hello(&mut control, &Hello::new(CAPABILITIES)).await?;
+        let capabilities = CAPABILITIES.negotiate(&hello)?;
 
         let info = messages::read_info(&mut stream).await?;
 
-        let framebuffer = Arc::new(Mutex::new(vec![0; info.display_size()].into_boxed_slice()));
+        let (input, mut events) = mpsc::unbounded_channel();
+        let (reconfigure, mut settings) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            // Ends once the viewer drops both senders or the connection goes
+            // away.
+            loop {
+                let result = tokio::select! {
+                    Some(event) = events.recv() => messages::write_input(&mut control, event).await,
+                    Some(settings) = settings.recv() => {
+                        messages::write_settings(&mut control, &settings).await
+                    }
+                    else => break,
+                };
 
-        let encode_buffer =
-            vec![0; lz4_flex::block::get_maximum_output_size(info.chunk_size())].into_boxed_slice();
+                if result.is_err() {
+                    break;
+                }
+            }
+        });
 
-        let decode_buffer = vec![0; info.chunk_size()].into_boxed_slice();
+        let (framebuffer, encode_buffer, decode_buffer) = buffers(&info);
 
-        sender.send(Message::Connected {
+        let recorder = match &options.record_dir {
+            Some(dir) => Some(Recorder::create(dir, sender.device, &info).await?),
+            None => None,
+        };
+
+        sender.send(DeviceMessage::Connected {
             info: info.clone(),
             framebuffer: Arc::clone(&framebuffer),
+            input: capabilities.contains(Capabilities::INPUT).then_some(input),
+            reconfigure: capabilities
+                .contains(Capabilities::RECONFIGURE)
+                .then_some(reconfigure),
         })?;
 
         Ok(Self {
-            stream,
+            stream: Counted {
+                inner: stream,
+                count: 0,
+            },
+            connection: connection.cloned(),
             info,
             sender,
             framebuffer,
             encode_buffer,
             decode_buffer,
+            rotation: Rotation::default(),
+            recorder,
+            record_dir: options.record_dir.clone(),
+            frame: Vec::new(),
+            last_frame: None,
         })
     }
 
     async fn run(mut self) -> anyhow::Result<()> {
         loop {
-            messages::read_frame(
-                &self.info,
-                &mut self.stream,
-                &mut self.encode_buffer,
-                &mut self.decode_buffer,
-                &self.framebuffer,
-            )
-            .await?;
-
-            self.sender.send(Message::Updated)?;
+            let start = self.stream.count;
+
+            let update = match &mut self.recorder {
+                Some(recorder) => {
+                    self.frame.clear();
+
+                    let update = messages::read_update(
+                        &self.info,
+                        &mut Tee {
+                            inner: &mut self.stream,
+                            copy: &mut self.frame,
+                        },
+                        &mut self.encode_buffer,
+                        &mut self.decode_buffer,
+                        &self.framebuffer,
+                    )
+                    .await?;
+
+                    if let Update::Frame(_) = update {
+                        recorder.write_frame(&self.frame).await?;
+                    }
+
+                    update
+                }
+                None => {
+                    messages::read_update(
+                        &self.info,
+                        &mut self.stream,
+                        &mut self.encode_buffer,
+                        &mut self.decode_buffer,
+                        &self.framebuffer,
+                    )
+                    .await?
+                }
+            };
+
+            let frame = match update {
+                Update::Frame(frame) => frame,
+                Update::Reconfigured(info) => {
+                    self.reconfigured(info).await?;
+                    continue;
+                }
+            };
+
+            if frame.rotation != self.rotation {
+                self.rotation = frame.rotation;
+                self.sender.send(DeviceMessage::Rotated(frame.rotation))?;
+            }
+
+            let now = Instant::now();
+
+            self.sender.send(DeviceMessage::Stats(FrameStats {
+                chunks: frame.chunks.len(),
+                pixels: frame.pixels,
+                bytes: self.stream.count - start,
+                decode_time: frame.decode_time,
+                interval: self.last_frame.map(|last| now - last),
+                link: self.connection.as_ref().and_then(LinkStats::sample),
+            }))?;
+            self.sender.send(DeviceMessage::Updated {
+                chunks: frame.chunks,
+            })?;
+
+            self.last_frame = Some(now);
+        }
+    }
+
+    async fn reconfigured(&mut self, info: messages::Info) -> anyhow::Result<()> {
+        println!("Kindle switched to {:?}", info.settings());
+
+        (self.framebuffer, self.encode_buffer, self.decode_buffer) = buffers(&info);
+
+        // Recordings have a single info, so the new settings get a new file.
+        if let Some(dir) = &self.record_dir {
+            self.recorder = Some(Recorder::create(dir, self.sender.device, &info).await?);
         }
+
+        self.sender.send(DeviceMessage::Reconfigured {
+            info: info.clone(),
+            framebuffer: Arc::clone(&self.framebuffer),
+        })?;
+
+        self.info = info;
+
+        Ok(())
     }
 }
+
+/// What [`DeviceMessage::Connected`] hands out, decoded into as frames come in.
+type Framebuffer = Arc<Mutex<Box<[u8]>>>;
+
+/// A blank framebuffer and the scratch space for decoding into it.
+fn buffers(info: &messages::Info) -> (Framebuffer, Box<[u8]>, Box<[u8]>) {
+    (
+        Arc::new(Mutex::new(vec![0; info.display_size()].into_boxed_slice())),
+        vec![0; codec::max_encoded_len(info.chunk_size())].into_boxed_slice(),
+        vec![0; info.chunk_size()].into_boxed_slice(),
+    )
+}
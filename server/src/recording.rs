@@ -0,0 +1,189 @@
+use std::{
+    io,
+    ops::Range,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use kinshare_shared::messages::{self, Info};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufWriter, ReadBuf},
+};
+
+use crate::DeviceId;
+
+/// Recordings start with this, followed by [`VERSION`], the kindle's id and
+/// its [`Info`]. After that every frame is a timestamp in microseconds since
+/// the recording started, its length, and the frame exactly as
+/// [`messages::read_frame`] received it.
+const MAGIC: [u8; 4] = *b"KNSR";
+const VERSION: u16 = 2;
+
+/// Appends every frame of one stream to a file.
+pub(crate) struct Recorder {
+    file: BufWriter<fs::File>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Start a new recording in `dir`, named after the kindle and the time.
+    pub(crate) async fn create(dir: &Path, device: DeviceId, info: &Info) -> anyhow::Result<Self> {
+        // Milliseconds, a reconfigured stream starts a new recording right
+        // after the last one.
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = dir.join(format!("{}-{timestamp}.knsr", device.fmt_short()));
+
+        fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("failed to create '{}'", dir.display()))?;
+
+        let file = fs::File::create(&path)
+            .await
+            .with_context(|| format!("failed to create '{}'", path.display()))?;
+
+        let mut file = BufWriter::new(file);
+
+        file.write_all(&MAGIC).await?;
+        file.write_u16(VERSION).await?;
+        file.write_all(device.as_bytes()).await?;
+        messages::write_info(&mut file, info).await?;
+        file.flush().await?;
+
+        println!("Recording to {}", path.display());
+
+        Ok(Self {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    pub(crate) async fn write_frame(&mut self, frame: &[u8]) -> anyhow::Result<()> {
+        self.file
+            .write_u64(self.start.elapsed().as_micros() as u64)
+            .await?;
+        self.file.write_u64(frame.len() as u64).await?;
+        self.file.write_all(frame).await?;
+
+        // Keep the file usable if we're killed mid-session.
+        self.file.flush().await?;
+
+        Ok(())
+    }
+}
+
+/// A recording loaded into memory, with the position of every frame.
+pub struct Recording {
+    pub device: DeviceId,
+    pub info: Info,
+    data: Vec<u8>,
+    frames: Vec<(Duration, Range<usize>)>,
+}
+
+impl Recording {
+    pub async fn open(path: &Path) -> anyhow::Result<Self> {
+        let data = fs::read(path)
+            .await
+            .with_context(|| format!("failed to read '{}'", path.display()))?;
+
+        let mut header = &data[..];
+
+        let mut magic = [0; 4];
+        header.read_exact(&mut magic).await?;
+        anyhow::ensure!(magic == MAGIC, "'{}' isn't a recording", path.display());
+
+        let version = header.read_u16().await?;
+        anyhow::ensure!(
+            version == VERSION,
+            "'{}' is a version {version} recording, expected {VERSION}",
+            path.display()
+        );
+
+        let mut device = [0; 32];
+        header.read_exact(&mut device).await?;
+        let device = DeviceId::from_bytes(&device)?;
+
+        let info = messages::read_info(&mut header).await?;
+
+        let mut frames = Vec::new();
+        let mut offset = data.len() - header.len();
+
+        while offset < data.len() {
+            let mut rest = &data[offset..];
+
+            let (Ok(time), Ok(len)) = (rest.read_u64().await, rest.read_u64().await) else {
+                break;
+            };
+
+            let start = offset + 16;
+            let end = start.saturating_add(len as usize);
+
+            if end > data.len() {
+                break;
+            }
+
+            frames.push((Duration::from_micros(time), start..end));
+            offset = end;
+        }
+
+        if offset != data.len() {
+            eprintln!(
+                "'{}' ends with a partial frame, it was probably cut off",
+                path.display()
+            );
+        }
+
+        Ok(Self {
+            device,
+            info,
+            data,
+            frames,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn length(&self) -> Duration {
+        self.frames.last().map_or(Duration::ZERO, |(time, _)| *time)
+    }
+
+    /// When frame `index` was received, and its bytes.
+    pub fn frame(&self, index: usize) -> (Duration, &[u8]) {
+        let (time, range) = &self.frames[index];
+
+        (*time, &self.data[range.clone()])
+    }
+}
+
+/// Copies everything read through it into `copy`, so frames can be recorded
+/// exactly as they came in.
+pub(crate) struct Tee<'a, R> {
+    pub(crate) inner: &'a mut R,
+    pub(crate) copy: &'a mut Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Tee<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut *self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = result {
+            self.copy.extend_from_slice(&buf.filled()[filled..]);
+        }
+
+        result
+    }
+}
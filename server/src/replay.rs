@@ -0,0 +1,245 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use kinshare_shared::{
+    codec,
+    messages::{self, Rotation},
+};
+use tokio::{
+    sync::mpsc,
+    time::{self, Instant},
+};
+
+use crate::{
+    Command, DeviceMessage, DeviceSender, FrameStats, Message, ReplayStatus, recording::Recording,
+};
+
+/// How often the position is reported while playing, frames alone can be
+/// minutes apart on an e-reader.
+const STATUS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Play a recording back as if the kindle was streaming it, reacting to the
+/// replay [`Command`]s.
+pub async fn replay(path: &Path, sender: mpsc::UnboundedSender<Message>) -> anyhow::Result<()> {
+    let recording = Recording::open(path).await?;
+
+    let (commands, mut command_receiver) = mpsc::unbounded_channel();
+
+    sender.send(Message::Ready(commands))?;
+    sender.send(Message::Devices(vec![recording.device]))?;
+
+    let sender = DeviceSender {
+        device: recording.device,
+        sender,
+    };
+
+    let mut player = Player::new(&recording, &sender)?;
+    let mut status = time::interval(STATUS_INTERVAL);
+
+    player.send_status()?;
+
+    loop {
+        let deadline = player.deadline();
+
+        tokio::select! {
+            _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                player.step().await?;
+
+                if player.next == recording.len() {
+                    player.set_paused(true);
+                    player.send_status()?;
+                }
+            }
+            _ = status.tick(), if !player.paused => player.send_status()?,
+            command = command_receiver.recv() => {
+                let Some(command) = command else {
+                    return Ok(());
+                };
+
+                match command {
+                    Command::Pause(paused) => {
+                        // Playing from the end starts over.
+                        if !paused && player.next == recording.len() {
+                            player.seek(Duration::ZERO).await?;
+                        }
+
+                        player.set_paused(paused);
+                    }
+                    Command::Seek(position) => player.seek(position).await?,
+                    Command::Speed(speed) => player.set_speed(speed),
+                    Command::Pair { .. } => {
+                        sender.sender.send(Message::PairingFailed(
+                            "can't pair while replaying a recording".to_owned(),
+                        ))?;
+                    }
+                }
+
+                player.send_status()?;
+            }
+        }
+    }
+}
+
+struct Player<'a> {
+    recording: &'a Recording,
+    sender: &'a DeviceSender,
+    framebuffer: Arc<Mutex<Box<[u8]>>>,
+    encode_buffer: Box<[u8]>,
+    decode_buffer: Box<[u8]>,
+    rotation: Rotation,
+    /// Index of the next frame to show.
+    next: usize,
+    paused: bool,
+    speed: f64,
+    /// Position in the recording at `resumed`, playback moves on from there.
+    resumed_at: Duration,
+    resumed: Instant,
+}
+
+impl<'a> Player<'a> {
+    fn new(recording: &'a Recording, sender: &'a DeviceSender) -> anyhow::Result<Self> {
+        let info = &recording.info;
+        let framebuffer = Arc::new(Mutex::new(vec![0; info.display_size()].into_boxed_slice()));
+
+        sender.send(DeviceMessage::Connected {
+            info: info.clone(),
+            framebuffer: Arc::clone(&framebuffer),
+            input: None,
+            reconfigure: None,
+        })?;
+
+        Ok(Self {
+            recording,
+            sender,
+            framebuffer,
+            encode_buffer: vec![0; codec::max_encoded_len(info.chunk_size())].into_boxed_slice(),
+            decode_buffer: vec![0; info.chunk_size()].into_boxed_slice(),
+            rotation: Rotation::default(),
+            next: 0,
+            paused: false,
+            speed: 1.0,
+            resumed_at: Duration::ZERO,
+            resumed: Instant::now(),
+        })
+    }
+
+    fn position(&self) -> Duration {
+        if self.paused {
+            self.resumed_at
+        } else {
+            self.resumed_at + self.resumed.elapsed().mul_f64(self.speed)
+        }
+        .min(self.recording.length())
+    }
+
+    /// When the next frame is due, `None` while paused or at the end.
+    fn deadline(&self) -> Option<Instant> {
+        if self.paused || self.next == self.recording.len() {
+            return None;
+        }
+
+        let (time, _) = self.recording.frame(self.next);
+
+        Some(self.resumed + time.saturating_sub(self.resumed_at).div_f64(self.speed))
+    }
+
+    /// Decode the next frame into the framebuffer.
+    async fn step(&mut self) -> anyhow::Result<()> {
+        let (_, mut frame) = self.recording.frame(self.next);
+
+        let bytes = frame.len();
+        let decoded = messages::read_frame(
+            &self.recording.info,
+            &mut frame,
+            &mut self.encode_buffer,
+            &mut self.decode_buffer,
+            &self.framebuffer,
+        )
+        .await?;
+
+        if decoded.rotation != self.rotation {
+            self.rotation = decoded.rotation;
+            self.sender.send(DeviceMessage::Rotated(decoded.rotation))?;
+        }
+
+        // Intervals as recorded, whatever the playback speed.
+        let interval = self
+            .next
+            .checked_sub(1)
+            .map(|previous| self.recording.frame(self.next).0 - self.recording.frame(previous).0);
+
+        self.next += 1;
+
+        self.sender.send(DeviceMessage::Stats(FrameStats {
+            chunks: decoded.chunks.len(),
+            pixels: decoded.pixels,
+            bytes,
+            decode_time: decoded.decode_time,
+            interval,
+            link: None,
+        }))?;
+        self.sender.send(DeviceMessage::Updated {
+            chunks: decoded.chunks,
+        })
+    }
+
+    /// Frames only carry the chunks that changed, so going backwards means
+    /// starting over from a blank screen.
+    async fn seek(&mut self, position: Duration) -> anyhow::Result<()> {
+        let position = position.min(self.recording.length());
+
+        let rewind = position < self.position();
+
+        if rewind {
+            self.framebuffer.lock().unwrap().fill(0);
+            self.next = 0;
+        }
+
+        while self.next < self.recording.len() && self.recording.frame(self.next).0 <= position {
+            self.step().await?;
+        }
+
+        // Clearing the framebuffer changed chunks no frame since touched.
+        if rewind {
+            let info = &self.recording.info;
+            let chunks = (0..info.chunks_per_y)
+                .flat_map(|y| (0..info.chunks_per_x).map(move |x| (x as u8, y as u8)))
+                .collect();
+
+            self.sender.send(DeviceMessage::Updated { chunks })?;
+        }
+
+        self.resumed_at = position;
+        self.resumed = Instant::now();
+
+        Ok(())
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.resumed_at = self.position();
+        self.resumed = Instant::now();
+        self.paused = paused;
+    }
+
+    fn set_speed(&mut self, speed: f64) {
+        if speed > 0.0 {
+            self.resumed_at = self.position();
+            self.resumed = Instant::now();
+            self.speed = speed;
+        }
+    }
+
+    fn send_status(&self) -> anyhow::Result<()> {
+        self.sender.sender.send(Message::Replay(ReplayStatus {
+            position: self.position(),
+            length: self.recording.length(),
+            paused: self.paused,
+            speed: self.speed,
+        }))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    fmt, fs,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, ensure};
+use kinshare_shared::messages::{Info, Rect, Rotation};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    /// Binary PGM (`P5`), readable by pretty much any image tool.
+    Pgm,
+    /// Bare 8-bit gray pixels, row by row.
+    Raw,
+}
+
+impl ImageFormat {
+    pub const ALL: [Self; 3] = [Self::Png, Self::Pgm, Self::Raw];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Pgm => "pgm",
+            Self::Raw => "raw",
+        }
+    }
+
+    /// Guess the format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension() == extension)
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Png => "PNG",
+            Self::Pgm => "PGM",
+            Self::Raw => "Raw",
+        })
+    }
+}
+
+/// An 8-bit grayscale image cut out of a kindle framebuffer.
+#[derive(Debug, Clone)]
+pub struct Screenshot {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Screenshot {
+    /// Copy `region` of `framebuffer` (everything if `None`), turned by
+    /// `rotation` so it comes out the way the viewer shows it. `region` is in
+    /// framebuffer coordinates.
+    pub fn capture(
+        info: &Info,
+        framebuffer: &[u8],
+        rotation: Rotation,
+        region: Option<Rect>,
+    ) -> anyhow::Result<Self> {
+        let region = region.unwrap_or(Rect {
+            x: 0,
+            y: 0,
+            width: info.display_width,
+            height: info.display_height,
+        });
+
+        ensure!(
+            region.width > 0
+                && region.height > 0
+                && region.right() <= info.display_width
+                && region.bottom() <= info.display_height,
+            "{region:?} doesn't fit in the {}x{} screen",
+            info.display_width,
+            info.display_height
+        );
+        ensure!(
+            framebuffer.len() == info.display_size(),
+            "framebuffer doesn't match the stream info"
+        );
+
+        let mut pixels = Vec::with_capacity(region.width * region.height);
+
+        for row in region.y..region.bottom() {
+            let start = row * info.display_width + region.x;
+            pixels.extend_from_slice(&framebuffer[start..start + region.width]);
+        }
+
+        let screenshot = Self {
+            width: region.width,
+            height: region.height,
+            pixels,
+        };
+
+        Ok(screenshot.rotated(rotation))
+    }
+
+    /// Turn the image clockwise, like the viewer does.
+    fn rotated(self, rotation: Rotation) -> Self {
+        let (width, height) = (self.width, self.height);
+
+        let (rotated_width, rotated_height) = if rotation.is_sideways() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+
+        let source = |x: usize, y: usize| match rotation {
+            Rotation::Upright => (x, y),
+            Rotation::Clockwise => (y, height - 1 - x),
+            Rotation::UpsideDown => (width - 1 - x, height - 1 - y),
+            Rotation::CounterClockwise => (width - 1 - y, x),
+        };
+
+        let pixels = (0..rotated_height)
+            .flat_map(|y| (0..rotated_width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (x, y) = source(x, y);
+                self.pixels[y * width + x]
+            })
+            .collect();
+
+        Self {
+            width: rotated_width,
+            height: rotated_height,
+            pixels,
+        }
+    }
+
+    pub fn save(&self, path: &Path, format: ImageFormat) -> anyhow::Result<()> {
+        let file = fs::File::create(path)
+            .with_context(|| format!("failed to create '{}'", path.display()))?;
+
+        let mut file = BufWriter::new(file);
+
+        match format {
+            ImageFormat::Png => {
+                let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+                encoder.set_color(png::ColorType::Grayscale);
+                encoder.set_depth(png::BitDepth::Eight);
+
+                let mut writer = encoder.write_header()?;
+                writer.write_image_data(&self.pixels)?;
+                writer.finish()?;
+            }
+            ImageFormat::Pgm => {
+                write!(file, "P5\n{} {}\n255\n", self.width, self.height)?;
+                file.write_all(&self.pixels)?;
+                file.flush()?;
+            }
+            ImageFormat::Raw => {
+                file.write_all(&self.pixels)?;
+                file.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compare pixel by pixel with `expected`, which has to be the same size.
+    /// Pixels may be off by up to `tolerance` gray levels.
+    pub fn compare(&self, expected: &Self, tolerance: u8) -> anyhow::Result<Difference> {
+        ensure!(
+            (self.width, self.height) == (expected.width, expected.height),
+            "screenshot is {}x{} but expected {}x{}",
+            self.width,
+            self.height,
+            expected.width,
+            expected.height
+        );
+
+        let mut difference = Difference {
+            width: self.width,
+            height: self.height,
+            differing: 0,
+            largest: 0,
+            image: Vec::with_capacity(self.pixels.len() * 3),
+        };
+
+        for (&actual, &expected) in self.pixels.iter().zip(&expected.pixels) {
+            let delta = actual.abs_diff(expected);
+            difference.largest = difference.largest.max(delta);
+
+            if delta > tolerance {
+                difference.differing += 1;
+                difference.image.extend([0xff, 0, 0]);
+            } else {
+                // Faded, so what differs stands out.
+                let faded = 0x80 + expected / 2;
+                difference.image.extend([faded; 3]);
+            }
+        }
+
+        Ok(difference)
+    }
+}
+
+/// How a [`Screenshot`] compared to the one it was expected to match.
+#[derive(Debug, Clone)]
+pub struct Difference {
+    pub width: usize,
+    pub height: usize,
+    /// Pixels off by more than the tolerance.
+    pub differing: usize,
+    /// The most any pixel is off by, tolerated or not.
+    pub largest: u8,
+    /// The expected image faded, with differing pixels in red.
+    image: Vec<u8>,
+}
+
+impl Difference {
+    /// Write the diff image as an RGB PNG.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let file = fs::File::create(path)
+            .with_context(|| format!("failed to create '{}'", path.display()))?;
+
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.image)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+}
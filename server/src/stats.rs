@@ -0,0 +1,69 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use iroh::endpoint::Connection;
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// How one frame went, sent along with every [`crate::DeviceMessage::Updated`]
+/// of a live stream or replay.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    /// Chunks the frame updated.
+    pub chunks: usize,
+    /// Pixels in those chunks, compared to `bytes` that's the compression
+    /// ratio.
+    pub pixels: usize,
+    /// Size of the frame on the wire, headers included.
+    pub bytes: usize,
+    /// Time spent decoding the chunks.
+    pub decode_time: Duration,
+    /// Time since the previous frame, `None` for the first of a stream.
+    pub interval: Option<Duration>,
+    /// The connection as of this frame, only known for iroh connections.
+    pub link: Option<LinkStats>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LinkStats {
+    pub rtt: Duration,
+    /// Going through a relay server instead of straight to the kindle.
+    pub relayed: bool,
+}
+
+impl LinkStats {
+    /// Stats of the path `connection` currently sends on, if it has one.
+    pub(crate) fn sample(connection: &Connection) -> Option<Self> {
+        let paths = connection.paths();
+        let path = paths.iter().find(|path| path.is_selected())?;
+
+        Some(Self {
+            rtt: path.rtt(),
+            relayed: path.is_relay(),
+        })
+    }
+}
+
+/// Counts the bytes read through it.
+pub(crate) struct Counted<R> {
+    pub(crate) inner: R,
+    pub(crate) count: usize,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Counted<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        self.count += buf.filled().len() - filled;
+
+        result
+    }
+}
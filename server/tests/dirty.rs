@@ -0,0 +1,80 @@
+use kinshare_server::DirtyChunks;
+use kinshare_shared::messages::{Info, Rect};
+
+/// A 64x48 screen in 8x8 chunks.
+fn info() -> Info {
+    Info {
+        display_width: 64,
+        display_height: 48,
+        chunks_per_x: 8,
+        chunks_per_y: 6,
+        thread_count: 1,
+        fps: 10.0,
+    }
+}
+
+#[test]
+fn merges_dirty_chunks() {
+    let mut dirty = DirtyChunks::new(&info());
+
+    assert_eq!(
+        dirty.take(),
+        [Rect {
+            x: 0,
+            y: 0,
+            width: 64,
+            height: 48
+        }]
+    );
+    assert!(dirty.is_empty());
+    assert!(dirty.take().is_empty());
+
+    // A block two rows high, a lone chunk, and some that aren't on the grid.
+    dirty.mark(&[(1, 2), (2, 2), (3, 2), (1, 3), (2, 3), (3, 3), (3, 3)]);
+    dirty.mark(&[(7, 5), (8, 0), (0, 6)]);
+
+    let mut rects = dirty.take();
+    rects.sort_by_key(|rect| (rect.y, rect.x));
+
+    assert_eq!(
+        rects,
+        [
+            Rect {
+                x: 8,
+                y: 16,
+                width: 24,
+                height: 16
+            },
+            Rect {
+                x: 56,
+                y: 40,
+                width: 8,
+                height: 8
+            },
+        ]
+    );
+
+    // Runs that don't line up stay apart.
+    dirty.mark(&[(0, 0), (1, 0), (1, 1)]);
+
+    let mut rects = dirty.take();
+    rects.sort_by_key(|rect| (rect.y, rect.x));
+
+    assert_eq!(
+        rects,
+        [
+            Rect {
+                x: 0,
+                y: 0,
+                width: 16,
+                height: 8
+            },
+            Rect {
+                x: 8,
+                y: 8,
+                width: 8,
+                height: 8
+            },
+        ]
+    );
+}
@@ -0,0 +1,87 @@
+use kinshare_server::Screenshot;
+use kinshare_shared::messages::{Info, Rect, Rotation};
+
+fn screenshot(width: usize, height: usize, pixels: &[u8]) -> Screenshot {
+    Screenshot {
+        width,
+        height,
+        pixels: pixels.to_vec(),
+    }
+}
+
+#[test]
+fn compares_screenshots() {
+    let expected = screenshot(3, 2, &[0, 64, 128, 192, 255, 255]);
+    let actual = screenshot(3, 2, &[0, 60, 128, 200, 255, 0]);
+
+    let difference = actual.compare(&expected, 4).unwrap();
+    assert_eq!((difference.width, difference.height), (3, 2));
+    assert_eq!(difference.differing, 2);
+    assert_eq!(difference.largest, 255);
+
+    // Everything within tolerance still reports how far off it was.
+    let difference = actual.compare(&actual, 0).unwrap();
+    assert_eq!((difference.differing, difference.largest), (0, 0));
+
+    let difference = screenshot(3, 2, &[10; 6])
+        .compare(&screenshot(3, 2, &[0; 6]), 10)
+        .unwrap();
+    assert_eq!((difference.differing, difference.largest), (0, 10));
+
+    assert!(actual.compare(&screenshot(2, 3, &[0; 6]), 255).is_err());
+}
+
+#[test]
+fn captures_turned_like_the_viewer() {
+    // 1 2 3
+    // 4 5 6
+    let info = Info {
+        display_width: 3,
+        display_height: 2,
+        chunks_per_x: 1,
+        chunks_per_y: 1,
+        thread_count: 1,
+        fps: 1.0,
+    };
+    let framebuffer = [1, 2, 3, 4, 5, 6];
+    let capture = |rotation, region| {
+        let screenshot = Screenshot::capture(&info, &framebuffer, rotation, region).unwrap();
+        (screenshot.width, screenshot.height, screenshot.pixels)
+    };
+
+    assert_eq!(
+        capture(Rotation::Upright, None),
+        (3, 2, vec![1, 2, 3, 4, 5, 6])
+    );
+    assert_eq!(
+        capture(Rotation::Clockwise, None),
+        (2, 3, vec![4, 1, 5, 2, 6, 3])
+    );
+    assert_eq!(
+        capture(Rotation::UpsideDown, None),
+        (3, 2, vec![6, 5, 4, 3, 2, 1])
+    );
+    assert_eq!(
+        capture(Rotation::CounterClockwise, None),
+        (2, 3, vec![3, 6, 2, 5, 1, 4])
+    );
+
+    // Regions are cut out of the framebuffer as sent, then turned.
+    let right = Rect {
+        x: 1,
+        y: 0,
+        width: 2,
+        height: 2,
+    };
+    assert_eq!(
+        capture(Rotation::Upright, Some(right)),
+        (2, 2, vec![2, 3, 5, 6])
+    );
+    assert_eq!(
+        capture(Rotation::Clockwise, Some(right)),
+        (2, 2, vec![5, 2, 6, 3])
+    );
+
+    let outside = Rect { width: 3, ..right };
+    assert!(Screenshot::capture(&info, &framebuffer, Rotation::Upright, Some(outside)).is_err());
+}
@@ -0,0 +1,218 @@
+use anyhow::{bail, ensure};
+use serde::Deserialize;
+
+use crate::messages::Capabilities;
+
+/// Level used for [`CodecId::Zstd`], a good deal smaller than LZ4 while still
+/// keeping up on the kindle's CPU.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Tag in front of every chunk on the wire, saying how it was encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum CodecId {
+    /// The baseline every peer supports, and what other codecs fall back to
+    /// when they can't take a chunk.
+    #[default]
+    Lz4 = 0,
+    /// Two pixels per byte, then LZ4. Only used for chunks drawn entirely in
+    /// the panel's 16 gray levels.
+    Gray4 = 1,
+    /// Run-length encoding, cheap and good at large flat areas.
+    Rle = 2,
+    Zstd = 3,
+}
+
+impl CodecId {
+    pub const ALL: [Self; 4] = [Self::Lz4, Self::Gray4, Self::Rle, Self::Zstd];
+
+    pub fn from_u8(tag: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|codec| *codec as u8 == tag)
+    }
+
+    /// The [`Hello`](crate::messages::Hello) flag advertising this codec.
+    pub const fn capability(self) -> Capabilities {
+        match self {
+            Self::Lz4 => Capabilities::CODEC_LZ4,
+            Self::Gray4 => Capabilities::CODEC_GRAY4,
+            Self::Rle => Capabilities::CODEC_RLE,
+            Self::Zstd => Capabilities::CODEC_ZSTD,
+        }
+    }
+
+    pub fn codec(self) -> &'static dyn Codec {
+        match self {
+            Self::Lz4 => &Lz4,
+            Self::Gray4 => &Gray4,
+            Self::Rle => &Rle,
+            Self::Zstd => &Zstd,
+        }
+    }
+}
+
+/// Turns the pixels of one chunk into bytes for the wire and back.
+pub trait Codec: Sync {
+    /// Encode `input` into `output`, returning the encoded length, or `None`
+    /// if this codec can't or shouldn't take it. `output` is at least
+    /// [`max_encoded_len`] bytes.
+    fn encode(&self, input: &[u8], output: &mut [u8]) -> Option<usize>;
+
+    /// Decode `input`, filling all of `output`.
+    fn decode(&self, input: &[u8], output: &mut [u8]) -> anyhow::Result<()>;
+}
+
+/// Space needed to encode `len` pixels with any codec.
+pub fn max_encoded_len(len: usize) -> usize {
+    lz4_flex::block::get_maximum_output_size(len)
+        .max(2 * len)
+        .max(zstd::zstd_safe::compress_bound(len))
+}
+
+/// Encode with `codec`, falling back to LZ4 when it passes.
+pub fn encode(codec: CodecId, input: &[u8], output: &mut [u8]) -> (CodecId, usize) {
+    if let Some(len) = codec.codec().encode(input, output) {
+        return (codec, len);
+    }
+
+    let len = Lz4.encode(input, output).expect("LZ4 takes every chunk");
+
+    (CodecId::Lz4, len)
+}
+
+struct Lz4;
+
+impl Codec for Lz4 {
+    fn encode(&self, input: &[u8], output: &mut [u8]) -> Option<usize> {
+        lz4_flex::block::compress_into(input, output).ok()
+    }
+
+    fn decode(&self, input: &[u8], output: &mut [u8]) -> anyhow::Result<()> {
+        let len = lz4_flex::block::decompress_into(input, output)?;
+
+        ensure!(
+            len == output.len(),
+            "LZ4 chunk decoded to {len} bytes, expected {}",
+            output.len()
+        );
+
+        Ok(())
+    }
+}
+
+struct Gray4;
+
+impl Codec for Gray4 {
+    fn encode(&self, input: &[u8], output: &mut [u8]) -> Option<usize> {
+        // Packed at the end of `output` and compressed into the space in
+        // front of it, which `max_encoded_len` leaves plenty of.
+        let split = output.len().checked_sub(input.len().div_ceil(2))?;
+        let (compressed, packed) = output.split_at_mut(split);
+
+        for (packed, pair) in packed.iter_mut().zip(input.chunks(2)) {
+            // Gray levels on the panel are multiples of 0x11, anything else
+            // can't be packed without losing detail.
+            if pair.iter().any(|&pixel| pixel >> 4 != pixel & 0xf) {
+                return None;
+            }
+
+            *packed = (pair[0] & 0xf0) | (pair.get(1).copied().unwrap_or(0) >> 4);
+        }
+
+        Lz4.encode(packed, compressed)
+    }
+
+    fn decode(&self, input: &[u8], output: &mut [u8]) -> anyhow::Result<()> {
+        let packed = output.len().div_ceil(2);
+
+        Lz4.decode(input, &mut output[..packed])?;
+
+        // Back to front, so every packed byte is read before the pixels it
+        // turns into overwrite it.
+        for i in (0..packed).rev() {
+            let pair = output[i];
+
+            if let Some(pixel) = output.get_mut(2 * i + 1) {
+                *pixel = (pair & 0xf) * 0x11;
+            }
+
+            output[2 * i] = (pair >> 4) * 0x11;
+        }
+
+        Ok(())
+    }
+}
+
+struct Rle;
+
+impl Codec for Rle {
+    fn encode(&self, input: &[u8], output: &mut [u8]) -> Option<usize> {
+        let mut len = 0;
+        let mut rest = input;
+
+        while let Some(&pixel) = rest.first() {
+            let run = rest
+                .iter()
+                .take(255)
+                .take_while(|&&other| other == pixel)
+                .count();
+
+            // Busy chunks come out bigger than they went in, LZ4 does better.
+            if len + 2 > input.len() {
+                return None;
+            }
+
+            output[len] = run as u8;
+            output[len + 1] = pixel;
+            len += 2;
+            rest = &rest[run..];
+        }
+
+        Some(len)
+    }
+
+    fn decode(&self, input: &[u8], output: &mut [u8]) -> anyhow::Result<()> {
+        ensure!(input.len().is_multiple_of(2), "RLE chunk has an odd length");
+
+        let mut written = 0;
+
+        for pair in input.chunks_exact(2) {
+            let run = pair[0] as usize;
+
+            if run == 0 || written + run > output.len() {
+                bail!("RLE chunk overflows its {} pixels", output.len());
+            }
+
+            output[written..written + run].fill(pair[1]);
+            written += run;
+        }
+
+        ensure!(
+            written == output.len(),
+            "RLE chunk decoded to {written} bytes, expected {}",
+            output.len()
+        );
+
+        Ok(())
+    }
+}
+
+struct Zstd;
+
+impl Codec for Zstd {
+    fn encode(&self, input: &[u8], output: &mut [u8]) -> Option<usize> {
+        zstd::bulk::compress_to_buffer(input, output, ZSTD_LEVEL).ok()
+    }
+
+    fn decode(&self, input: &[u8], output: &mut [u8]) -> anyhow::Result<()> {
+        let len = zstd::bulk::decompress_to_buffer(input, output)?;
+
+        ensure!(
+            len == output.len(),
+            "zstd chunk decoded to {len} bytes, expected {}",
+            output.len()
+        );
+
+        Ok(())
+    }
+}
@@ -1 +1,13 @@
 pub const ALPN: &[u8] = b"skeary/screenshare/0";
+pub const PAIRING_ALPN: &[u8] = b"skeary/screenshare/pair/1";
+
+/// mDNS service kindles advertise themselves under while in pairing mode, so
+/// the desktop can find them without knowing their id yet.
+pub const PAIRING_SERVICE: &str = "kinshare-pair";
+
+/// Sent first on every stream so a peer speaking something else is caught
+/// before any sizes are trusted.
+pub const MAGIC: [u8; 4] = *b"KNSH";
+
+/// Bumped whenever the wire format changes in an incompatible way.
+pub const PROTOCOL_VERSION: u16 = 2;
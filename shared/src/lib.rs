@@ -1,3 +1,6 @@
+pub mod codec;
 pub mod consts;
 pub mod messages;
+pub mod pairing;
+pub mod transport;
 pub mod utils;
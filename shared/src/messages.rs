@@ -1,12 +1,276 @@
 use std::{
+    fmt,
     hash::Hasher,
+    io,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use iroh::endpoint::{RecvStream, SendStream};
 use rustc_hash::FxHasher;
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    codec::{self, CodecId},
+    consts::{MAGIC, PROTOCOL_VERSION},
+    pairing::Commitment,
+};
+
+/// Feature flags each side advertises in its [`Hello`].
+///
+/// Flags are grouped by kind, and a session needs at least one shared flag in
+/// every group that is mandatory (codecs and pixel formats). LZ4 is the
+/// baseline codec the others fall back to, so it has to be shared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const CODEC_LZ4: Self = Self(1 << 0);
+    pub const CODEC_GRAY4: Self = Self(1 << 1);
+    pub const CODEC_RLE: Self = Self(1 << 2);
+    pub const CODEC_ZSTD: Self = Self(1 << 3);
+    pub const CODECS: Self = Self(0xff);
+
+    pub const FORMAT_GRAY8: Self = Self(1 << 8);
+    pub const FORMATS: Self = Self(0xff << 8);
+
+    pub const INPUT: Self = Self(1 << 16);
+    /// Chunks may be sent XORed against what the peer already has.
+    pub const DELTA: Self = Self(1 << 17);
+    /// The desktop may ask for new [`Settings`] mid-stream.
+    pub const RECONFIGURE: Self = Self(1 << 18);
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Agree on the flags both sides support, failing if the peer speaks a
+    /// different protocol version or nothing usable is left over.
+    pub fn negotiate(self, remote: &Hello) -> Result<Self, HandshakeError> {
+        if remote.version != PROTOCOL_VERSION {
+            return Err(HandshakeError::VersionMismatch {
+                local: PROTOCOL_VERSION,
+                remote: remote.version,
+            });
+        }
+
+        let common = self.intersection(remote.capabilities);
+
+        if !common.contains(Self::CODEC_LZ4) {
+            return Err(HandshakeError::NoCommonCodec {
+                local: self,
+                remote: remote.capabilities,
+            });
+        }
+
+        if !common.intersects(Self::FORMATS) {
+            return Err(HandshakeError::NoCommonPixelFormat {
+                local: self,
+                remote: remote.capabilities,
+            });
+        }
+
+        Ok(common)
+    }
+}
+
+/// First message on a stream, sent by both sides before anything else.
+#[derive(Debug, Clone)]
+pub struct Hello {
+    pub version: u16,
+    pub capabilities: Capabilities,
+}
+
+impl Hello {
+    pub fn new(capabilities: Capabilities) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    BadMagic([u8; 4]),
+    VersionMismatch {
+        local: u16,
+        remote: u16,
+    },
+    NoCommonCodec {
+        local: Capabilities,
+        remote: Capabilities,
+    },
+    NoCommonPixelFormat {
+        local: Capabilities,
+        remote: Capabilities,
+    },
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic(magic) => {
+                write!(
+                    f,
+                    "peer is not speaking the kinshare protocol (got {magic:02x?})"
+                )
+            }
+            Self::VersionMismatch { local, remote } => write!(
+                f,
+                "protocol version mismatch: we speak v{local}, the peer speaks v{remote}, update both sides"
+            ),
+            Self::NoCommonCodec { local, remote } => write!(
+                f,
+                "peer doesn't support LZ4, the baseline codec (ours: {:#x}, theirs: {:#x})",
+                local.bits() & Capabilities::CODECS.bits(),
+                remote.bits() & Capabilities::CODECS.bits()
+            ),
+            Self::NoCommonPixelFormat { local, remote } => write!(
+                f,
+                "no pixel format in common (ours: {:#x}, theirs: {:#x})",
+                (local.bits() & Capabilities::FORMATS.bits()) >> 8,
+                (remote.bits() & Capabilities::FORMATS.bits()) >> 8
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+/// Anything wrong with what the peer sent. Decoding never trusts the wire, a
+/// corrupt or hostile peer gets one of these instead of crashing us.
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// The stream failed or ended in the middle of a message.
+    Io(io::Error),
+    Handshake(HandshakeError),
+    InfoLength(u32),
+    InvalidInfo(&'static str),
+    UnknownPairingResult(u8),
+    UnknownControl(u8),
+    UnknownTouchPhase(u8),
+    UnknownEncoding(u8),
+    InvalidRotation(u8),
+    ChunkOutOfRange {
+        x: u8,
+        y: u8,
+    },
+    ChunkTooLarge {
+        len: u64,
+        max: usize,
+    },
+    /// The chunk's codec couldn't make sense of its bytes.
+    CorruptChunk {
+        x: u8,
+        y: u8,
+        codec: CodecId,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                f.write_str("stream ended in the middle of a message")
+            }
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Handshake(err) => write!(f, "{err}"),
+            Self::InfoLength(len) => write!(f, "invalid stream info length: {len}"),
+            Self::InvalidInfo(reason) => write!(f, "invalid stream info: {reason}"),
+            Self::UnknownPairingResult(result) => write!(f, "unknown pairing result: {result}"),
+            Self::UnknownControl(tag) => write!(f, "unknown control message: {tag}"),
+            Self::UnknownTouchPhase(phase) => write!(f, "unknown touch phase: {phase}"),
+            Self::UnknownEncoding(tag) => write!(f, "chunk uses unknown codec {tag:#x}"),
+            Self::InvalidRotation(rotation) => write!(f, "invalid frame rotation: {rotation}"),
+            Self::ChunkOutOfRange { x, y } => {
+                write!(f, "chunk ({x}, {y}) is outside the chunk grid")
+            }
+            Self::ChunkTooLarge { len, max } => {
+                write!(f, "chunk is {len} bytes encoded, at most {max} fit")
+            }
+            Self::CorruptChunk {
+                x,
+                y,
+                codec,
+                reason,
+            } => write!(f, "chunk ({x}, {y}) isn't valid {codec:?}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Handshake(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProtocolError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<HandshakeError> for ProtocolError {
+    fn from(err: HandshakeError) -> Self {
+        Self::Handshake(err)
+    }
+}
+
+pub async fn write_hello(
+    stream: &mut (impl AsyncWrite + Unpin),
+    hello: &Hello,
+) -> anyhow::Result<()> {
+    stream.write_all(&MAGIC).await?;
+    stream.write_u16(hello.version).await?;
+    stream.write_u32(hello.capabilities.bits()).await?;
+
+    stream.flush().await?;
+
+    Ok(())
+}
+
+pub async fn read_hello(stream: &mut (impl AsyncRead + Unpin)) -> Result<Hello, ProtocolError> {
+    let mut magic = [0; 4];
+    stream.read_exact(&mut magic).await?;
+
+    if magic != MAGIC {
+        return Err(HandshakeError::BadMagic(magic).into());
+    }
+
+    let version = stream.read_u16().await?;
+    let capabilities = Capabilities::from_bits(stream.read_u32().await?);
+
+    Ok(Hello {
+        version,
+        capabilities,
+    })
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Info {
@@ -23,50 +287,418 @@ impl Info {
         self.display_width * self.display_height
     }
 
+    /// Widest a chunk can be. Panels that don't divide evenly get chunks that
+    /// differ by at most one pixel, see [`Info::chunk_rect`].
     pub fn chunk_width(&self) -> usize {
-        self.display_width / self.chunks_per_x
+        self.display_width.div_ceil(self.chunks_per_x)
     }
 
+    /// Tallest a chunk can be.
     pub fn chunk_height(&self) -> usize {
-        self.display_height / self.chunks_per_y
+        self.display_height.div_ceil(self.chunks_per_y)
     }
 
     pub fn chunk_count(&self) -> usize {
         self.chunks_per_x * self.chunks_per_y
     }
 
+    /// Upper bound on the number of pixels in a chunk.
     pub fn chunk_size(&self) -> usize {
         self.chunk_width() * self.chunk_height()
     }
+
+    /// The parts the desktop can change mid-stream.
+    pub fn settings(&self) -> Settings {
+        Settings {
+            chunks_per_x: self.chunks_per_x,
+            chunks_per_y: self.chunks_per_y,
+            thread_count: self.thread_count,
+            fps: self.fps,
+        }
+    }
+
+    /// The same display with `settings` applied, unchecked.
+    pub fn with_settings(&self, settings: Settings) -> Self {
+        Self {
+            chunks_per_x: settings.chunks_per_x,
+            chunks_per_y: settings.chunks_per_y,
+            thread_count: settings.thread_count,
+            fps: settings.fps,
+            ..self.clone()
+        }
+    }
+
+    /// Check that the stream can actually be run like this, whoever sent it.
+    pub fn validate(&self) -> Result<(), ProtocolError> {
+        let display = 1..=MAX_DISPLAY_SIDE;
+
+        if !display.contains(&(self.display_width as u64))
+            || !display.contains(&(self.display_height as u64))
+        {
+            return Err(ProtocolError::InvalidInfo("display size out of range"));
+        }
+
+        if !(1..=MAX_CHUNKS_PER_SIDE.min(self.display_width as u64))
+            .contains(&(self.chunks_per_x as u64))
+            || !(1..=MAX_CHUNKS_PER_SIDE.min(self.display_height as u64))
+                .contains(&(self.chunks_per_y as u64))
+        {
+            return Err(ProtocolError::InvalidInfo(
+                "chunk grid doesn't fit the display",
+            ));
+        }
+
+        if !(1..=MAX_THREAD_COUNT).contains(&self.thread_count) {
+            return Err(ProtocolError::InvalidInfo("thread count out of range"));
+        }
+
+        if !(self.fps.is_finite() && self.fps > 0.0) {
+            return Err(ProtocolError::InvalidInfo("fps isn't a positive number"));
+        }
+
+        Ok(())
+    }
+
+    /// Pixel bounds of the chunk at grid position `x`, `y`.
+    pub fn chunk_rect(&self, x: usize, y: usize) -> Rect {
+        let left = x * self.display_width / self.chunks_per_x;
+        let right = (x + 1) * self.display_width / self.chunks_per_x;
+        let top = y * self.display_height / self.chunks_per_y;
+        let bottom = (y + 1) * self.display_height / self.chunks_per_y;
+
+        Rect {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
 }
 
-pub async fn write_info(stream: &mut SendStream, info: &Info) -> anyhow::Result<()> {
-    stream.write_u64(info.display_width as u64).await?;
-    stream.write_u64(info.display_height as u64).await?;
-    stream.write_u64(info.chunks_per_x as u64).await?;
-    stream.write_u64(info.chunks_per_y as u64).await?;
-    stream.write_u64(info.thread_count as u64).await?;
-    stream.write_f64(info.fps).await?;
+/// Sent first by the desktop on the pairing ALPN, see [`crate::pairing`].
+pub async fn write_pairing_commitment(
+    stream: &mut (impl AsyncWrite + Unpin),
+    hash: &[u8; 32],
+) -> anyhow::Result<()> {
+    stream.write_all(&MAGIC).await?;
+    stream.write_all(hash).await?;
+
+    stream.flush().await?;
 
     Ok(())
 }
 
-pub async fn read_info(stream: &mut RecvStream) -> anyhow::Result<Info> {
-    let display_width = stream.read_u64().await? as usize;
-    let display_height = stream.read_u64().await? as usize;
-    let chunks_per_x = stream.read_u64().await? as usize;
-    let chunks_per_y = stream.read_u64().await? as usize;
-    let thread_count = stream.read_u64().await? as usize;
-    let fps = stream.read_f64().await?;
-
-    Ok(Info {
-        display_width,
-        display_height,
-        chunks_per_x,
-        chunks_per_y,
-        thread_count,
-        fps,
-    })
+pub async fn read_pairing_commitment(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<[u8; 32], ProtocolError> {
+    let mut magic = [0; 4];
+    stream.read_exact(&mut magic).await?;
+
+    if magic != MAGIC {
+        return Err(HandshakeError::BadMagic(magic).into());
+    }
+
+    let mut hash = [0; 32];
+    stream.read_exact(&mut hash).await?;
+
+    Ok(hash)
+}
+
+/// The kindle's answer to a commitment.
+pub async fn write_pairing_proof(
+    stream: &mut (impl AsyncWrite + Unpin),
+    proof: &[u8; 32],
+) -> anyhow::Result<()> {
+    stream.write_all(proof).await?;
+
+    stream.flush().await?;
+
+    Ok(())
+}
+
+pub async fn read_pairing_proof(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<[u8; 32], ProtocolError> {
+    let mut proof = [0; 32];
+    stream.read_exact(&mut proof).await?;
+
+    Ok(proof)
+}
+
+/// The desktop's commitment opened, once the kindle's proof checked out.
+pub async fn write_pairing_opening(
+    stream: &mut (impl AsyncWrite + Unpin),
+    commitment: &Commitment,
+) -> anyhow::Result<()> {
+    stream.write_all(&commitment.nonce).await?;
+    stream.write_all(&commitment.proof).await?;
+
+    stream.flush().await?;
+
+    Ok(())
+}
+
+pub async fn read_pairing_opening(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<Commitment, ProtocolError> {
+    let mut commitment = Commitment {
+        nonce: [0; 32],
+        proof: [0; 32],
+    };
+
+    stream.read_exact(&mut commitment.nonce).await?;
+    stream.read_exact(&mut commitment.proof).await?;
+
+    Ok(commitment)
+}
+
+pub async fn write_pairing_result(
+    stream: &mut (impl AsyncWrite + Unpin),
+    accepted: bool,
+) -> anyhow::Result<()> {
+    stream.write_u8(accepted as u8).await?;
+
+    stream.flush().await?;
+
+    Ok(())
+}
+
+pub async fn read_pairing_result(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<bool, ProtocolError> {
+    match stream.read_u8().await? {
+        0 => Ok(false),
+        1 => Ok(true),
+        result => Err(ProtocolError::UnknownPairingResult(result)),
+    }
+}
+
+/// Encoded size of the fields [`write_info`] knows about. Newer peers may send
+/// a longer body, the extra bytes are skipped.
+const INFO_LEN: u32 = 6 * 8;
+const MAX_INFO_LEN: u32 = 4096;
+
+/// Largest panel side accepted from a peer. Well above any kindle, but small
+/// enough that a bogus [`Info`] can't have us allocate gigabytes.
+const MAX_DISPLAY_SIDE: u64 = 8192;
+
+/// Chunk coordinates go over the wire as a single byte.
+const MAX_CHUNKS_PER_SIDE: u64 = 256;
+
+/// Far more than any kindle has cores, but keeps a peer from asking for
+/// millions of threads.
+const MAX_THREAD_COUNT: usize = 64;
+
+pub async fn write_info(stream: &mut (impl AsyncWrite + Unpin), info: &Info) -> anyhow::Result<()> {
+    let mut body = Vec::with_capacity(INFO_LEN as usize);
+
+    body.write_u64(info.display_width as u64).await?;
+    body.write_u64(info.display_height as u64).await?;
+    body.write_u64(info.chunks_per_x as u64).await?;
+    body.write_u64(info.chunks_per_y as u64).await?;
+    body.write_u64(info.thread_count as u64).await?;
+    body.write_f64(info.fps).await?;
+
+    stream.write_u32(body.len() as u32).await?;
+    stream.write_all(&body).await?;
+
+    stream.flush().await?;
+
+    Ok(())
+}
+
+pub async fn read_info(stream: &mut (impl AsyncRead + Unpin)) -> Result<Info, ProtocolError> {
+    let len = stream.read_u32().await?;
+
+    if !(INFO_LEN..=MAX_INFO_LEN).contains(&len) {
+        return Err(ProtocolError::InfoLength(len));
+    }
+
+    let mut body = vec![0; len as usize];
+    stream.read_exact(&mut body).await?;
+
+    let mut body = &body[..];
+
+    let info = Info {
+        display_width: read_usize(&mut body).await?,
+        display_height: read_usize(&mut body).await?,
+        chunks_per_x: read_usize(&mut body).await?,
+        chunks_per_y: read_usize(&mut body).await?,
+        thread_count: read_usize(&mut body).await?,
+        fps: body.read_f64().await?,
+    };
+
+    info.validate()?;
+
+    Ok(info)
+}
+
+/// Read a `u64` that has to fit in a `usize`, which isn't a given on the
+/// kindle. Anything that big is out of range for every field anyway.
+async fn read_usize(stream: &mut (impl AsyncRead + Unpin)) -> Result<usize, ProtocolError> {
+    usize::try_from(stream.read_u64().await?)
+        .map_err(|_| ProtocolError::InvalidInfo("field doesn't fit in memory"))
+}
+
+/// Stream parameters the desktop can ask the kindle to switch to without
+/// reconnecting. The display itself stays as it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub chunks_per_x: usize,
+    pub chunks_per_y: usize,
+    pub thread_count: usize,
+    pub fps: f64,
+}
+
+/// Panel orientation, using the kernel's `FB_ROTATE_*` numbering.
+///
+/// The framebuffer is always sent as laid out in memory, the viewer turns it
+/// clockwise by this much to show it upright.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum Rotation {
+    #[default]
+    Upright = 0,
+    Clockwise = 1,
+    UpsideDown = 2,
+    CounterClockwise = 3,
+}
+
+impl Rotation {
+    pub fn from_raw(raw: u32) -> Self {
+        match raw % 4 {
+            0 => Self::Upright,
+            1 => Self::Clockwise,
+            2 => Self::UpsideDown,
+            _ => Self::CounterClockwise,
+        }
+    }
+
+    /// How much further clockwise this is turned than `base`.
+    pub fn relative_to(self, base: Self) -> Self {
+        Self::from_raw((self as u32).wrapping_sub(base as u32))
+    }
+
+    /// Whether width and height trade places on screen.
+    pub fn is_sideways(self) -> bool {
+        matches!(self, Self::Clockwise | Self::CounterClockwise)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchPhase {
+    Down,
+    Move,
+    Up,
+}
+
+/// Input from the viewer, sent back to the kindle to be replayed there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    /// A single finger, in framebuffer pixels as laid out in memory.
+    Touch { phase: TouchPhase, x: u16, y: u16 },
+    /// A linux `KEY_*` code.
+    Key { code: u16, pressed: bool },
+}
+
+pub async fn write_input(
+    stream: &mut (impl AsyncWrite + Unpin),
+    event: InputEvent,
+) -> anyhow::Result<()> {
+    match event {
+        InputEvent::Touch { phase, x, y } => {
+            stream.write_u8(0).await?;
+            stream.write_u8(phase as u8).await?;
+            stream.write_u16(x).await?;
+            stream.write_u16(y).await?;
+        }
+        InputEvent::Key { code, pressed } => {
+            stream.write_u8(1).await?;
+            stream.write_u16(code).await?;
+            stream.write_u8(pressed as u8).await?;
+        }
+    }
+
+    stream.flush().await?;
+
+    Ok(())
+}
+
+/// Ask the kindle to switch to `settings`, see [`Capabilities::RECONFIGURE`].
+pub async fn write_settings(
+    stream: &mut (impl AsyncWrite + Unpin),
+    settings: &Settings,
+) -> anyhow::Result<()> {
+    stream.write_u8(2).await?;
+    stream.write_u64(settings.chunks_per_x as u64).await?;
+    stream.write_u64(settings.chunks_per_y as u64).await?;
+    stream.write_u64(settings.thread_count as u64).await?;
+    stream.write_f64(settings.fps).await?;
+
+    stream.flush().await?;
+
+    Ok(())
+}
+
+/// Everything the desktop sends once the stream is running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Input(InputEvent),
+    Reconfigure(Settings),
+}
+
+/// Read the next [`write_input`] or [`write_settings`]. Settings still have to
+/// be checked against the display with [`Info::validate`].
+pub async fn read_control(stream: &mut (impl AsyncRead + Unpin)) -> Result<Control, ProtocolError> {
+    let event = match stream.read_u8().await? {
+        0 => {
+            let phase = match stream.read_u8().await? {
+                0 => TouchPhase::Down,
+                1 => TouchPhase::Move,
+                2 => TouchPhase::Up,
+                phase => return Err(ProtocolError::UnknownTouchPhase(phase)),
+            };
+            let x = stream.read_u16().await?;
+            let y = stream.read_u16().await?;
+
+            InputEvent::Touch { phase, x, y }
+        }
+        1 => {
+            let code = stream.read_u16().await?;
+            let pressed = stream.read_u8().await? != 0;
+
+            InputEvent::Key { code, pressed }
+        }
+        2 => {
+            return Ok(Control::Reconfigure(Settings {
+                chunks_per_x: read_usize(stream).await?,
+                chunks_per_y: read_usize(stream).await?,
+                thread_count: read_usize(stream).await?,
+                fps: stream.read_f64().await?,
+            }));
+        }
+        tag => return Err(ProtocolError::UnknownControl(tag)),
+    };
+
+    Ok(Control::Input(event))
 }
 
 pub struct Chunk {
@@ -75,19 +707,50 @@ pub struct Chunk {
     pub hash: u64,
     pub encoded: Box<[u8]>,
     pub encoded_len: usize,
+    /// What `encoded` was encoded with.
+    pub encoding: Encoding,
+    /// Pixels as last sent, kept around for delta encoding.
+    pub previous: Option<Box<[u8]>>,
     pub updated: bool,
 }
 
+/// How a chunk was encoded, sent as its tag byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Encoding {
+    pub codec: CodecId,
+    /// The pixels were XORed with the chunk's previous contents first, so
+    /// unchanged areas turn into runs of zeros.
+    pub delta: bool,
+}
+
+impl Encoding {
+    const DELTA: u8 = 0x80;
+
+    pub fn tag(self) -> u8 {
+        self.codec as u8 | if self.delta { Self::DELTA } else { 0 }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        Some(Self {
+            codec: CodecId::from_u8(tag & !Self::DELTA)?,
+            delta: tag & Self::DELTA != 0,
+        })
+    }
+}
+
 pub async fn write_frame(
-    stream: &mut SendStream,
+    stream: &mut (impl AsyncWrite + Unpin),
+    rotation: Rotation,
     chunks: &mut [Chunk],
     updated: usize,
 ) -> anyhow::Result<()> {
+    stream.write_u8(rotation as u8).await?;
     stream.write_u64(updated as u64).await?;
 
     for chunk in chunks.iter_mut().filter(|c| c.updated) {
         stream.write_u8(chunk.x as u8).await?;
         stream.write_u8(chunk.y as u8).await?;
+        stream.write_u8(chunk.encoding.tag()).await?;
         stream.write_u64(chunk.encoded_len as u64).await?;
         stream
             .write_all(&chunk.encoded[..chunk.encoded_len])
@@ -96,26 +759,50 @@ pub async fn write_frame(
         chunk.updated = false;
     }
 
+    stream.flush().await?;
+
     Ok(())
 }
 
+/// Sent instead of a frame's rotation byte when the stream switches to new
+/// settings, followed by the new [`Info`].
+const RECONFIGURED: u8 = 0x80;
+
+/// Tell the desktop that every frame from now on follows `info`. Chunks are
+/// all sent whole again afterwards, onto a blank framebuffer.
+pub async fn write_reconfigured(
+    stream: &mut (impl AsyncWrite + Unpin),
+    info: &Info,
+) -> anyhow::Result<()> {
+    stream.write_u8(RECONFIGURED).await?;
+
+    write_info(stream, info).await
+}
+
+/// Encode `chunk` if its contents changed, with the codec and delta mode in
+/// `encoding` where possible. Chunks fall back to LZ4 when the codec can't
+/// take them, and are sent whole the first time.
 pub fn encode_chunk(
     info: &Info,
     file_offset: usize,
     framebuffer: &[u8],
     encode: &mut [u8],
+    encoding: Encoding,
     chunk: &mut Chunk,
 ) {
-    let frame_top_left_x = chunk.x * info.chunk_width();
-    let frame_top_left_y = chunk.y * info.chunk_height();
+    let rect = info.chunk_rect(chunk.x, chunk.y);
+    let rows = || {
+        (0..rect.height).map(|row| {
+            let frame_start = (rect.x + (rect.y + row) * info.display_width) - file_offset;
 
-    let mut hasher = FxHasher::default();
+            &framebuffer[frame_start..frame_start + rect.width]
+        })
+    };
 
-    for row in 0..info.chunk_height() {
-        let frame_start =
-            (frame_top_left_x + (frame_top_left_y + row) * info.display_width) - file_offset;
+    let mut hasher = FxHasher::default();
 
-        hasher.write(&framebuffer[frame_start..frame_start + info.chunk_width()]);
+    for row in rows() {
+        hasher.write(row);
     }
 
     let hash = hasher.finish();
@@ -125,68 +812,197 @@ pub fn encode_chunk(
         return;
     }
 
-    for row in 0..info.chunk_height() {
-        let frame_start =
-            (frame_top_left_x + (frame_top_left_y + row) * info.display_width) - file_offset;
-        let buffer_start = row * info.chunk_width();
+    for (i, row) in rows().enumerate() {
+        let buffer_start = i * rect.width;
 
-        encode[buffer_start..buffer_start + info.chunk_width()]
-            .copy_from_slice(&framebuffer[frame_start..frame_start + info.chunk_width()]);
+        encode[buffer_start..buffer_start + rect.width].copy_from_slice(row);
     }
 
+    let encode = &mut encode[..rect.width * rect.height];
+
+    let delta = match &mut chunk.previous {
+        Some(previous) if encoding.delta => {
+            for (pixel, previous) in encode.iter_mut().zip(previous.iter_mut()) {
+                let current = *pixel;
+
+                *pixel ^= *previous;
+                *previous = current;
+            }
+
+            true
+        }
+        _ => {
+            if encoding.delta {
+                chunk.previous = Some(encode.to_vec().into_boxed_slice());
+            }
+
+            false
+        }
+    };
+
+    let (codec, encoded_len) = codec::encode(encoding.codec, encode, &mut chunk.encoded);
+
     chunk.hash = hash;
-    chunk.encoded_len = lz4_flex::block::compress_into(encode, &mut chunk.encoded)
-        .expect("compression shouldn't fail");
+    chunk.encoding = Encoding { codec, delta };
+    chunk.encoded_len = encoded_len;
 
     chunk.updated = true;
 }
 
+/// What a frame held, as far as [`read_frame`] could tell.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub rotation: Rotation,
+    /// Grid coordinates of the chunks the frame updated, in the order they
+    /// arrived.
+    pub chunks: Vec<(u8, u8)>,
+    /// Pixels in those chunks, what they'd take up unencoded.
+    pub pixels: usize,
+    /// Time spent decoding the chunks, not counting waiting for them.
+    pub decode_time: Duration,
+}
+
+/// Read one frame into `framebuffer`. `encoded` has room for
+/// [`codec::max_encoded_len`] of a chunk, `decoded` for
+/// [`Info::chunk_size`] pixels.
 pub async fn read_frame(
     info: &Info,
-    stream: &mut RecvStream,
+    stream: &mut (impl AsyncRead + Unpin),
     encoded: &mut [u8],
     decoded: &mut [u8],
     framebuffer: &Arc<Mutex<Box<[u8]>>>,
-) -> anyhow::Result<()> {
+) -> Result<Frame, ProtocolError> {
+    let rotation = read_rotation(stream.read_u8().await?)?;
+
+    read_chunks(info, rotation, stream, encoded, decoded, framebuffer).await
+}
+
+/// What came next on a stream that may be reconfigured.
+#[derive(Debug, Clone)]
+pub enum Update {
+    Frame(Frame),
+    /// Nothing was decoded, the buffers have to be resized for the new
+    /// [`Info`] before the next read.
+    Reconfigured(Info),
+}
+
+/// Like [`read_frame`], but also accepts a [`write_reconfigured`] in place
+/// of a frame.
+pub async fn read_update(
+    info: &Info,
+    stream: &mut (impl AsyncRead + Unpin),
+    encoded: &mut [u8],
+    decoded: &mut [u8],
+    framebuffer: &Arc<Mutex<Box<[u8]>>>,
+) -> Result<Update, ProtocolError> {
+    match stream.read_u8().await? {
+        RECONFIGURED => Ok(Update::Reconfigured(read_info(stream).await?)),
+        rotation => {
+            let rotation = read_rotation(rotation)?;
+
+            Ok(Update::Frame(
+                read_chunks(info, rotation, stream, encoded, decoded, framebuffer).await?,
+            ))
+        }
+    }
+}
+
+/// The byte a frame starts with, only the kernel's four rotations are valid.
+fn read_rotation(rotation: u8) -> Result<Rotation, ProtocolError> {
+    match rotation {
+        0..=3 => Ok(Rotation::from_raw(rotation as u32)),
+        rotation => Err(ProtocolError::InvalidRotation(rotation)),
+    }
+}
+
+async fn read_chunks(
+    info: &Info,
+    rotation: Rotation,
+    stream: &mut (impl AsyncRead + Unpin),
+    encoded: &mut [u8],
+    decoded: &mut [u8],
+    framebuffer: &Arc<Mutex<Box<[u8]>>>,
+) -> Result<Frame, ProtocolError> {
     let chunks = stream.read_u64().await?;
+    let mut frame = Frame {
+        rotation,
+        chunks: Vec::new(),
+        pixels: 0,
+        decode_time: Duration::ZERO,
+    };
 
     for _ in 0..chunks {
         let x = stream.read_u8().await?;
         let y = stream.read_u8().await?;
-        let encoded_len = stream.read_u64().await? as usize;
-        stream.read_exact(&mut encoded[..encoded_len]).await?;
+        let tag = stream.read_u8().await?;
+        let encoding = Encoding::from_tag(tag).ok_or(ProtocolError::UnknownEncoding(tag))?;
+        let len = stream.read_u64().await?;
 
-        decode_chunk(
-            info,
-            x,
-            y,
-            &encoded[..encoded_len],
-            decoded,
-            &mut framebuffer.lock().unwrap(),
-        );
+        let max = encoded.len();
+        let chunk = usize::try_from(len)
+            .ok()
+            .and_then(|len| encoded.get_mut(..len))
+            .ok_or(ProtocolError::ChunkTooLarge { len, max })?;
+
+        stream.read_exact(chunk).await?;
+
+        let start = Instant::now();
+
+        decode_chunk(info, x, y, encoding, chunk, decoded, framebuffer)?;
+
+        let rect = info.chunk_rect(x as usize, y as usize);
+
+        frame.chunks.push((x, y));
+        frame.pixels += rect.width * rect.height;
+        frame.decode_time += start.elapsed();
     }
 
-    Ok(())
+    Ok(frame)
 }
 
 pub fn decode_chunk(
     info: &Info,
     x: u8,
     y: u8,
+    encoding: Encoding,
     encoded: &[u8],
     decoded: &mut [u8],
-    framebuffer: &mut [u8],
-) {
-    lz4_flex::block::decompress_into(encoded, decoded).expect("decompression shouldn't fail");
-
-    let frame_top_left_x = x as usize * info.chunk_width();
-    let frame_top_left_y = y as usize * info.chunk_height();
+    framebuffer: &Mutex<Box<[u8]>>,
+) -> Result<(), ProtocolError> {
+    if x as usize >= info.chunks_per_x || y as usize >= info.chunks_per_y {
+        return Err(ProtocolError::ChunkOutOfRange { x, y });
+    }
 
-    for row in 0..info.chunk_height() {
-        let frame_start = frame_top_left_x + (frame_top_left_y + row) * info.display_width;
-        let buffer_start = row * info.chunk_width();
+    let rect = info.chunk_rect(x as usize, y as usize);
+    let decoded = &mut decoded[..rect.width * rect.height];
 
-        framebuffer[frame_start..frame_start + info.chunk_width()]
-            .copy_from_slice(&decoded[buffer_start..buffer_start + info.chunk_width()]);
+    encoding
+        .codec
+        .codec()
+        .decode(encoded, decoded)
+        .map_err(|err| ProtocolError::CorruptChunk {
+            x,
+            y,
+            codec: encoding.codec,
+            reason: format!("{err:#}"),
+        })?;
+
+    // Only locked for the copy, whoever draws the framebuffer is waiting on
+    // it.
+    let mut framebuffer = framebuffer.lock().unwrap();
+
+    for (row, decoded) in decoded.chunks_exact(rect.width).enumerate() {
+        let frame_start = rect.x + (rect.y + row) * info.display_width;
+        let frame_row = &mut framebuffer[frame_start..frame_start + rect.width];
+
+        if encoding.delta {
+            for (pixel, delta) in frame_row.iter_mut().zip(decoded) {
+                *pixel ^= delta;
+            }
+        } else {
+            frame_row.copy_from_slice(decoded);
+        }
     }
+
+    Ok(())
 }
@@ -0,0 +1,70 @@
+//! Proving to each other that both sides of a pairing know the code on the
+//! kindle's screen, without giving it away to whoever else answers on the
+//! local network.
+//!
+//! Proofs are MACs over both endpoint ids keyed by the code, so one is only
+//! good for this desktop and this kindle and can't be relayed. Six digits are
+//! quick to brute-force from a proof though, so the desktop first commits to
+//! its proof, the kindle then sends its own, and the desktop only opens its
+//! commitment once the kindle's checks out. A fake kindle never sees anything
+//! that depends on the code, and a fake desktop that works the code out from
+//! the kindle's proof is stuck with the commitment it made without it. The
+//! kindle moves on to a new code after every failed attempt, so a code worked
+//! out that way is no good for another try.
+
+/// Which side a proof is from, so the kindle's can't be sent back as the
+/// desktop's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Desktop,
+    Kindle,
+}
+
+/// What `role` sends to show it knows `code`, when pairing the `desktop` and
+/// `kindle` endpoints.
+pub fn proof(code: u32, role: Role, desktop: &[u8; 32], kindle: &[u8; 32]) -> [u8; 32] {
+    let key = blake3::derive_key("kinshare pairing code v1", &code.to_be_bytes());
+
+    *blake3::Hasher::new_keyed(&key)
+        .update(&[role as u8])
+        .update(desktop)
+        .update(kindle)
+        .finalize()
+        .as_bytes()
+}
+
+/// Compares in constant time, so timing doesn't tell how close a guess was.
+pub fn proofs_match(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    blake3::Hash::from_bytes(*a) == blake3::Hash::from_bytes(*b)
+}
+
+/// The desktop's proof, hidden behind a random nonce until it's opened.
+#[derive(Debug, Clone)]
+pub struct Commitment {
+    pub nonce: [u8; 32],
+    pub proof: [u8; 32],
+}
+
+impl Commitment {
+    pub fn new(proof: [u8; 32]) -> anyhow::Result<Self> {
+        let mut nonce = [0; 32];
+        getrandom::fill(&mut nonce)?;
+
+        Ok(Self { nonce, proof })
+    }
+
+    /// What's sent in place of the proof. Without the nonce it says nothing
+    /// about the code.
+    pub fn hash(&self) -> [u8; 32] {
+        *blake3::Hasher::new()
+            .update(&self.nonce)
+            .update(&self.proof)
+            .finalize()
+            .as_bytes()
+    }
+
+    /// Whether this is what `hash` was committed to, and carries `expected`.
+    pub fn opens(&self, hash: &[u8; 32], expected: &[u8; 32]) -> bool {
+        proofs_match(&self.hash(), hash) && proofs_match(&self.proof, expected)
+    }
+}
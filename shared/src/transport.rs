@@ -0,0 +1,144 @@
+//! What the protocol runs over. Normally that's a QUIC stream from iroh, but
+//! anything that moves bytes both ways works: every message is read from an
+//! [`AsyncRead`] and written to an [`AsyncWrite`], flushing at the end so
+//! buffered writers send it right away.
+
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, BufWriter},
+    net::{TcpListener, TcpStream},
+};
+
+/// Port kindles listen on over plain TCP unless told otherwise.
+pub const DEFAULT_TCP_PORT: u16 = 7878;
+
+pub type Reader = Box<dyn AsyncRead + Send + Unpin>;
+pub type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Split a two-way stream for the protocol functions. Messages are written a
+/// field at a time, so the writer is buffered.
+pub fn split(stream: impl AsyncRead + AsyncWrite + Send + 'static) -> (Reader, Writer) {
+    let (reader, writer) = tokio::io::split(stream);
+
+    (Box::new(reader), Box::new(BufWriter::new(writer)))
+}
+
+/// A connection without iroh, for networks where QUIC is blocked or the
+/// kindle is only reachable over a USB network link. Neither is encrypted or
+/// authenticated, anyone who can reach the socket can watch.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Direct {
+    /// `host:port`, the port defaults to [`DEFAULT_TCP_PORT`].
+    Tcp { address: String },
+    #[cfg(unix)]
+    Unix { path: PathBuf },
+}
+
+impl Direct {
+    /// Parse the command line form, `tcp:<address>` or `unix:<path>`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.split_once(':')? {
+            ("tcp", address) if !address.is_empty() => Some(Self::Tcp {
+                address: address.to_owned(),
+            }),
+            #[cfg(unix)]
+            ("unix", path) if !path.is_empty() => Some(Self::Unix { path: path.into() }),
+            _ => None,
+        }
+    }
+
+    pub async fn connect(&self) -> io::Result<(Reader, Writer)> {
+        match self {
+            Self::Tcp { address } => {
+                let stream = TcpStream::connect(with_default_port(address)).await?;
+                // Frames are flushed whole, there's nothing to gain from waiting.
+                stream.set_nodelay(true)?;
+
+                Ok(split(stream))
+            }
+            #[cfg(unix)]
+            Self::Unix { path } => Ok(split(tokio::net::UnixStream::connect(path).await?)),
+        }
+    }
+
+    pub async fn bind(&self) -> io::Result<DirectListener> {
+        match self {
+            Self::Tcp { address } => Ok(DirectListener::Tcp(
+                TcpListener::bind(with_default_port(address)).await?,
+            )),
+            #[cfg(unix)]
+            Self::Unix { path } => {
+                // A socket left behind by a previous run would make binding fail.
+                match std::fs::remove_file(path) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+
+                Ok(DirectListener::Unix(tokio::net::UnixListener::bind(path)?))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Direct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp { address } => write!(f, "tcp:{address}"),
+            #[cfg(unix)]
+            Self::Unix { path } => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+fn with_default_port(address: &str) -> String {
+    if address.parse::<SocketAddr>().is_ok() {
+        return address.to_owned();
+    }
+
+    // Bare IPs need brackets around IPv6 before a port can go on, host names
+    // only have a colon if they already come with a port.
+    match address.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, DEFAULT_TCP_PORT).to_string(),
+        Err(_) if address.contains(':') => address.to_owned(),
+        Err(_) => format!("{address}:{DEFAULT_TCP_PORT}"),
+    }
+}
+
+pub enum DirectListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl DirectListener {
+    /// Wait for the next connection, along with a description of the peer.
+    pub async fn accept(&self) -> io::Result<(Reader, Writer, String)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, address) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+
+                let (reader, writer) = split(stream);
+
+                Ok((reader, writer, address.to_string()))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                let path = listener.local_addr()?;
+                let path = path.as_pathname().unwrap_or(Path::new("?"));
+
+                let (reader, writer) = split(stream);
+
+                Ok((reader, writer, format!("unix:{}", path.display())))
+            }
+        }
+    }
+}
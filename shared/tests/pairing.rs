@@ -0,0 +1,49 @@
+use futures_lite::future;
+use kinshare_shared::{
+    messages,
+    pairing::{self, Commitment, Role},
+};
+
+const DESKTOP: [u8; 32] = [1; 32];
+const KINDLE: [u8; 32] = [2; 32];
+
+#[test]
+fn proofs_are_bound_to_code_role_and_peers() {
+    let proof = pairing::proof(123456, Role::Kindle, &DESKTOP, &KINDLE);
+
+    assert!(pairing::proofs_match(
+        &proof,
+        &pairing::proof(123456, Role::Kindle, &DESKTOP, &KINDLE)
+    ));
+
+    for other in [
+        pairing::proof(123457, Role::Kindle, &DESKTOP, &KINDLE),
+        pairing::proof(123456, Role::Desktop, &DESKTOP, &KINDLE),
+        pairing::proof(123456, Role::Kindle, &[3; 32], &KINDLE),
+        pairing::proof(123456, Role::Kindle, &DESKTOP, &[3; 32]),
+    ] {
+        assert!(!pairing::proofs_match(&proof, &other));
+    }
+}
+
+#[test]
+fn commitments_open_only_to_what_was_committed() {
+    let proof = pairing::proof(123456, Role::Desktop, &DESKTOP, &KINDLE);
+    let commitment = Commitment::new(proof).unwrap();
+    let hash = commitment.hash();
+
+    // The opening goes over the wire like any other message.
+    let mut bytes = Vec::new();
+    future::block_on(messages::write_pairing_opening(&mut bytes, &commitment)).unwrap();
+    let opening = future::block_on(messages::read_pairing_opening(&mut &bytes[..])).unwrap();
+
+    assert!(opening.opens(&hash, &proof));
+
+    // A right proof behind someone else's commitment, or the other way around.
+    let wrong = pairing::proof(654321, Role::Desktop, &DESKTOP, &KINDLE);
+    assert!(!Commitment::new(proof).unwrap().opens(&hash, &proof));
+    assert!(!opening.opens(&hash, &wrong));
+
+    // Nonces are fresh, the same proof never commits to the same hash.
+    assert_ne!(Commitment::new(proof).unwrap().hash(), hash);
+}
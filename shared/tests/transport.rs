@@ -0,0 +1,92 @@
+//! The protocol only needs something to read from and write to, not iroh.
+
+use futures_lite::future;
+use kinshare_shared::{
+    messages::{self, Capabilities, Control, Hello, InputEvent, Settings},
+    transport::{self, Direct},
+};
+
+#[test]
+fn messages_over_duplex() {
+    let (kindle, desktop) = tokio::io::duplex(64);
+    let (mut kindle_reader, mut kindle_writer) = transport::split(kindle);
+    let (mut desktop_reader, mut desktop_writer) = transport::split(desktop);
+
+    future::block_on(async {
+        let info = messages::Info {
+            display_width: 40,
+            display_height: 30,
+            chunks_per_x: 4,
+            chunks_per_y: 3,
+            thread_count: 1,
+            fps: 10.0,
+        };
+
+        // Messages are bigger than the duplex buffer, so writing only gets
+        // through if both ends make progress together.
+        let (written, hello) = future::zip(
+            async {
+                messages::write_hello(&mut kindle_writer, &Hello::new(Capabilities::INPUT)).await?;
+                messages::write_info(&mut kindle_writer, &info).await
+            },
+            messages::read_hello(&mut desktop_reader),
+        )
+        .await;
+        written.unwrap();
+
+        assert_eq!(hello.unwrap().capabilities, Capabilities::INPUT);
+
+        let received = messages::read_info(&mut desktop_reader).await.unwrap();
+        assert_eq!((received.display_width, received.chunks_per_y), (40, 3));
+
+        // KEY_HOME.
+        let home = InputEvent::Key {
+            code: 102,
+            pressed: true,
+        };
+        messages::write_input(&mut desktop_writer, home)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            messages::read_control(&mut kindle_reader).await.unwrap(),
+            Control::Input(home)
+        );
+
+        let settings = Settings {
+            chunks_per_x: 2,
+            chunks_per_y: 6,
+            thread_count: 3,
+            fps: 12.5,
+        };
+        messages::write_settings(&mut desktop_writer, &settings)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            messages::read_control(&mut kindle_reader).await.unwrap(),
+            Control::Reconfigure(settings)
+        );
+    });
+}
+
+#[test]
+fn parses_addresses() {
+    assert_eq!(
+        Direct::parse("tcp:192.168.15.244"),
+        Some(Direct::Tcp {
+            address: "192.168.15.244".to_owned()
+        })
+    );
+    assert_eq!(Direct::parse("tcp:"), None);
+    assert_eq!(Direct::parse("udp:localhost"), None);
+    assert_eq!(Direct::parse("localhost"), None);
+
+    #[cfg(unix)]
+    assert_eq!(
+        Direct::parse("unix:/tmp/kinshare.sock"),
+        Some(Direct::Unix {
+            path: "/tmp/kinshare.sock".into()
+        })
+    );
+}